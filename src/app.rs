@@ -1,24 +1,85 @@
-use std::{collections::VecDeque, error::Error};
+use std::{collections::VecDeque, error::Error, time::Duration};
 
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{self, Event, KeyEventKind};
 use ratatui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::Stylize,
+    widgets::Paragraph,
     Frame, Terminal,
 };
 
 use crate::{
-    config::Config,
-    email::{self, new_session, TlsSession},
-    widget::{Focusable, Widget, Widgets},
+    cache::Cache,
+    command::{self, Setting, UserCommand},
+    compose::{self, Draft, ReplyKind},
+    config::{Account, Config, DateFormat},
+    email::{MailFlag, SpecialUse, Transfer},
+    keymap::{Action, Keymap, Resolved, Scope},
+    query,
+    watcher::{WatchEvent, Watcher},
+    widget::{
+        help::hint_line,
+        notifications::{Level, Notice},
+        search::SearchKind,
+        sidebar::Folder,
+        Focusable, Widget, Widgets,
+    },
+    worker::{Command, ConnectionState, WorkerEvent, Workers},
 };
 
+/// How long to wait for terminal input before checking on the worker again.
+const TICK_RATE: Duration = Duration::from_millis(50);
+
+/// Actions handled by [`App::act`], listed first in the help.
+const GLOBAL_ACTIONS: [Action; 10] = [
+    Action::Help,
+    Action::Quit,
+    Action::NextPane,
+    Action::PrevPane,
+    Action::Filter,
+    Action::Search,
+    Action::Command,
+    Action::Compose,
+    Action::Log,
+    Action::Cancel,
+];
+
 #[derive(Clone, PartialEq, Eq)]
 pub enum LoadType {
     FetchEmails,
     FetchPreview,
     FetchInboxes,
     Login,
+    Search,
+    FetchMore,
+    SetFlag(MailFlag, bool),
+    Expunge,
+    Transfer(Transfer, String),
+    Archive,
+    Trash,
+    /// Run a line typed at the `:` prompt
+    Command(String),
+    /// Fetch the message selected in the list, or shown in the preview if that is where it was
+    /// asked for, to write back about
+    Reply(ReplyKind, Focusable),
+    Send,
+}
+
+impl LoadType {
+    /// Whether a new load of this type makes a pending one obsolete. Actions change state on the
+    /// server and must all go through.
+    pub fn supersedes(&self) -> bool {
+        !matches!(
+            self,
+            LoadType::SetFlag(..)
+                | LoadType::Expunge
+                | LoadType::Transfer(..)
+                | LoadType::Archive
+                | LoadType::Trash
+                | LoadType::Send
+        )
+    }
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -38,19 +99,59 @@ impl Default for Mode {
 pub struct App {
     pub widgets: Widgets,
     should_quit: bool,
+    /// Message to open in the editor, once the terminal can be given up
+    composing: Option<Draft>,
+    /// The last message written, until it is sent
+    unsent: Option<Draft>,
 }
 
-#[derive(Default)]
 pub struct Context {
     pub mode: Mode,
     pub config: Config,
-    pub errors: VecDeque<String>,
-    session: Option<TlsSession>,
+    pub accounts: Vec<Account>,
+    pub keymap: Keymap,
+    /// Errors and messages for the user, not shown yet
+    pub notices: VecDeque<Notice>,
+    pub workers: Workers,
+    /// Second connection per account reporting changes to the listed mailbox, started once
+    /// logged in
+    pub watchers: Vec<Option<Watcher>>,
 }
 
 impl Context {
+    pub fn new(workers: Workers, keymap: Keymap) -> Context {
+        Context {
+            mode: Mode::default(),
+            config: Config::default(),
+            accounts: vec![],
+            keymap,
+            notices: VecDeque::new(),
+            workers,
+            watchers: vec![],
+        }
+    }
+
+    /// Title to show in place of a widget's own while `load` is in flight.
+    pub fn loading_title(&self, load: &LoadType) -> Option<String> {
+        if !self.workers.is_loading(load) {
+            return None;
+        }
+        Some(match self.workers.progress(load) {
+            Some((done, total)) => format!("Loading... ({}/{})", done, total),
+            None => "Loading...".to_owned(),
+        })
+    }
+
+    /// Show `err` in a popup until acknowledged.
     pub fn show_error<S: ToString>(&mut self, err: S) {
-        self.errors.push_front(err.to_string());
+        self.notices
+            .push_back(Notice::new(Level::Error, err.to_string()));
+    }
+
+    /// Show `msg` briefly, for things worth knowing but not acting on.
+    pub fn notify<S: ToString>(&mut self, msg: S) {
+        self.notices
+            .push_back(Notice::new(Level::Info, msg.to_string()));
     }
 }
 
@@ -58,7 +159,11 @@ impl App {
     pub fn draw(&mut self, f: &mut Frame, ctx: &mut Context) {
         let layout_vert = Layout::new(
             Direction::Vertical,
-            [Constraint::Length(3), Constraint::Min(1)],
+            [
+                Constraint::Length(3),
+                Constraint::Min(1),
+                Constraint::Length(1),
+            ],
         )
         .split(f.size());
         let layout = Layout::new(
@@ -75,204 +180,790 @@ impl App {
         self.widgets.sidebar.draw(f, layout[0], ctx);
         self.widgets.email.draw(f, layout[1], ctx);
         self.widgets.preview.draw(f, layout[2], ctx);
+        if let Mode::Focus(Focusable::Picker(_)) = ctx.mode {
+            self.widgets.picker.draw(f, f.size(), ctx);
+        }
+        if let Mode::Focus(Focusable::Help) = ctx.mode {
+            self.widgets.help.draw(f, f.size(), ctx);
+        }
+        self.widgets.notifications.draw_toasts(f, layout_vert[1]);
+        self.widgets.notifications.draw(f, f.size(), ctx);
+        match ctx.mode {
+            Mode::Focus(Focusable::Command) => self.widgets.command.draw(f, layout_vert[2], ctx),
+            _ => self.draw_hints(f, layout_vert[2], ctx),
+        }
+    }
+
+    /// Keys of the focused widget, with a reminder of where to find the rest.
+    fn draw_hints(&self, f: &mut Frame, area: Rect, ctx: &Context) {
+        let Some((_, mut keys)) = self.widgets.help(&ctx.mode, &ctx.keymap) else {
+            return;
+        };
+        // Typing `?` into the search box only types it
+        if !matches!(
+            ctx.mode,
+            Mode::Focus(Focusable::Search | Focusable::Help) | Mode::Error(_)
+        ) {
+            keys.splice(0..0, ctx.keymap.describe(Scope::Global, &[Action::Help]));
+        }
+        let pending = ctx.keymap.pending();
+        if !pending.is_empty() {
+            keys.insert(0, (pending, "..."));
+        }
+        f.render_widget(Paragraph::new(hint_line(&keys)).dark_gray(), area);
     }
 
     pub async fn run_app<B: Backend>(
         &mut self,
         terminal: &mut Terminal<B>,
+        conf: Config,
+        keymap: Keymap,
     ) -> Result<(), Box<dyn Error>> {
-        let mut ctx = Context::default();
-        let conf = match Config::load() {
-            Ok(c) => c,
-            Err(e) => return Err(e.into()),
-        };
+        let mut ctx = Context::new(Workers::spawn(conf.accounts().len()), keymap);
         conf.apply(&mut ctx);
+        ctx.watchers = ctx.accounts.iter().map(|_| None).collect();
+        self.widgets
+            .sidebar
+            .set_accounts(ctx.accounts.iter().map(|a| a.name.to_owned()).collect());
+        if let Some(f) = ctx.config.date_format.invalid() {
+            ctx.show_error(format!("Invalid date format \"{}\", using defaults", f));
+            ctx.config.date_format = DateFormat::default();
+        }
+        // Show what was there last time while logging in
+        for (account, a) in ctx.accounts.iter().enumerate() {
+            if let Some(inboxes) = Cache::open(a).and_then(|c| c.inboxes()) {
+                self.widgets.sidebar.set_inboxes(account, inboxes);
+            }
+        }
+        let folders = self.folders(&ctx);
+        for (account, inbox) in &folders {
+            let cached = Cache::open(&ctx.accounts[*account]).and_then(|c| c.listing(inbox));
+            if let Some((mut entries, mut listing)) = cached {
+                listing.account = *account;
+                entries.iter_mut().for_each(|e| e.account = *account);
+                match folders.len() {
+                    1 => self.widgets.email.set_entries(entries, listing),
+                    _ => self.widgets.email.merge(entries, listing),
+                }
+            }
+        }
         ctx.mode = Mode::Loading(LoadType::Login);
         while !self.should_quit {
-            if !ctx.errors.is_empty() {
-                ctx.mode = Mode::Error(ctx.errors.pop_front().unwrap_or_default());
+            if let Mode::Loading(load) = ctx.mode.clone() {
+                ctx.mode = Mode::Focus(match load {
+                    LoadType::FetchPreview => Focusable::Preview,
+                    LoadType::Command(_) => self.widgets.command.return_to.clone(),
+                    LoadType::Reply(_, ref from) => from.clone(),
+                    _ => Focusable::Emails,
+                });
+                self.load(load, &mut ctx);
+            }
+            if let Some(draft) = self.composing.take() {
+                self.edit(draft, terminal, &mut ctx)?;
+            }
+
+            while let Some((account, load, evt)) = ctx.workers.try_recv() {
+                self.on_worker(account, load, evt, &mut ctx);
+            }
+            for account in 0..ctx.watchers.len() {
+                while let Some(evt) = ctx.watchers[account].as_ref().and_then(|w| w.try_recv()) {
+                    self.on_watch(account, evt, &mut ctx);
+                }
+            }
+            while let Some(notice) = ctx.notices.pop_front() {
+                self.widgets.notifications.push(notice);
+            }
+            self.widgets.notifications.show_errors(&mut ctx);
+
+            // A page still in flight, or a listing about to be replaced, makes another premature
+            let listing = [LoadType::FetchMore, LoadType::FetchEmails, LoadType::Search];
+            if !listing.iter().any(|l| ctx.workers.is_loading(l)) {
+                self.load(LoadType::FetchMore, &mut ctx);
             }
 
-            // get_help(app, w);
             terminal.draw(|f| self.draw(f, &mut ctx))?;
-            if let Mode::Loading(load) = &ctx.mode {
-                let load = load.clone();
-                ctx.mode = Mode::Focus(Focusable::Emails);
-                match load {
-                    LoadType::FetchEmails => {
-                        let session = match &mut ctx.session {
-                            Some(s) => s,
-                            None => {
-                                ctx.show_error("Not logged in");
-                                continue;
-                            }
-                        };
-                        ctx.mode = Mode::Focus(Focusable::Emails);
-                        let inbox_idx = self
-                            .widgets
-                            .sidebar
-                            .table
-                            .state
-                            .selected()
-                            .unwrap_or_default();
-                        let inbox = self
-                            .widgets
-                            .sidebar
-                            .table
-                            .items
-                            .get(inbox_idx)
-                            .map(|i| i.to_owned())
-                            .unwrap_or("INBOX".to_owned());
-                        let subs = match email::top_messages(session, inbox, 100) {
-                            Ok(body) => body,
-                            Err(e) => {
-                                ctx.show_error(e);
-                                continue;
-                            }
-                        };
-                        let subs = match subs {
-                            Some(body) => body,
-                            None => vec![],
+
+            if !event::poll(TICK_RATE)? {
+                continue;
+            }
+            let evt = event::read()?;
+            let resolved = match (&evt, Scope::of(&ctx.mode)) {
+                (Event::Key(key), Some(scope)) if key.kind == KeyEventKind::Press => {
+                    ctx.keymap.resolve(scope, key)
+                }
+                _ => Resolved::Unbound,
+            };
+            match resolved {
+                Resolved::Action(action) => {
+                    self.widgets.act(action, &mut ctx);
+                    self.act(action, &mut ctx);
+                }
+                Resolved::Pending => {}
+                Resolved::Unbound => self.widgets.on(evt, &mut ctx),
+            }
+        }
+
+        ctx.workers.shutdown();
+
+        Ok(())
+    }
+
+    /// Start writing a message from the shown account, or get back the last one if it didn't go
+    /// out.
+    fn compose(&mut self, ctx: &mut Context) {
+        if !ctx.workers.is_loading(&LoadType::Send) {
+            if let Some(draft) = self.unsent.take() {
+                self.composing = Some(draft);
+                return;
+            }
+        }
+        let account = self.widgets.sidebar.account();
+        if self.can_send(account, ctx) {
+            self.composing = Some(Draft::new(account, &ctx.accounts[account]));
+        }
+    }
+
+    /// Whether `account` has a server to send mail through, telling the user if not.
+    fn can_send(&self, account: usize, ctx: &mut Context) -> bool {
+        match ctx.accounts.get(account) {
+            Some(a) if a.smtp.is_some() => true,
+            Some(_) => {
+                ctx.show_error("No SMTP server to send with, set [smtp] for the account in config");
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Hand the terminal over to the editor for `draft`, then send what was written.
+    fn edit<B: Backend>(
+        &mut self,
+        mut draft: Draft,
+        terminal: &mut Terminal<B>,
+        ctx: &mut Context,
+    ) -> Result<(), Box<dyn Error>> {
+        let text = draft.text();
+        crate::restore_terminal()?;
+        let edited = compose::edit(&text);
+        crate::setup_terminal()?;
+        terminal.clear()?;
+        let edited = match edited {
+            Ok(edited) => edited,
+            Err(e) => {
+                ctx.show_error(format!("Editing the message failed: {}", e));
+                return Ok(());
+            }
+        };
+        // Like with git commit, saving nothing or leaving a new message as it was gives up on it
+        if edited.trim().is_empty() || (edited == text && !draft.was_edited()) {
+            self.unsent = None;
+            ctx.notify("Message discarded");
+            return Ok(());
+        }
+        let message = draft.update(edited).and_then(|_| draft.to_message());
+        let account = draft.account;
+        self.unsent = Some(draft);
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                ctx.show_error(format!(
+                    "Message not sent: {}. Composing again reopens it",
+                    e
+                ));
+                return Ok(());
+            }
+        };
+        let cmd = Command::Send {
+            account: Box::new(ctx.accounts[account].clone()),
+            message: Box::new(message),
+        };
+        ctx.workers.send(account, LoadType::Send, cmd);
+        ctx.notify("Sending...");
+        Ok(())
+    }
+
+    /// Accounts and mailboxes the message list shows, several for the combined view.
+    fn folders(&self, ctx: &Context) -> Vec<(usize, String)> {
+        match self.widgets.sidebar.selected() {
+            Some(Folder::All) => (0..ctx.accounts.len())
+                .map(|a| (a, "INBOX".to_owned()))
+                .collect(),
+            Some(Folder::Account(a)) => vec![(*a, "INBOX".to_owned())],
+            Some(Folder::Mailbox(a, inbox)) => vec![(*a, inbox.to_owned())],
+            None => vec![(0, "INBOX".to_owned())],
+        }
+    }
+
+    /// Account, inbox, UIDVALIDITY and UIDs of the messages the next action applies to, one
+    /// group per mailbox as marked messages may come from several accounts in the combined view.
+    fn targets(&self) -> Vec<(usize, String, u32, Vec<u32>)> {
+        let mut groups: Vec<(usize, String, u32, Vec<u32>)> = vec![];
+        for e in self.widgets.email.targets() {
+            match groups
+                .iter_mut()
+                .find(|g| g.0 == e.account && g.1 == e.inbox)
+            {
+                Some(group) => group.3.push(e.uid),
+                None => groups.push((e.account, e.inbox.to_owned(), e.uid_validity, vec![e.uid])),
+            }
+        }
+        groups
+    }
+
+    /// Move or copy the targets to the mailbox `dest` names for their account. Those of accounts
+    /// it names none for stay where they are, and their accounts are returned.
+    fn transfer<F: Fn(usize) -> Option<String>>(
+        &self,
+        load: LoadType,
+        op: Transfer,
+        dest: F,
+        ctx: &mut Context,
+    ) -> Vec<usize> {
+        let mut skipped = vec![];
+        for (account, inbox, uid_validity, uids) in self.targets() {
+            let Some(dest) = dest(account) else {
+                skipped.push(account);
+                continue;
+            };
+            let cmd = Command::Transfer {
+                inbox,
+                uid_validity,
+                uids,
+                dest,
+                op,
+            };
+            ctx.workers.send(account, load.clone(), cmd);
+        }
+        skipped
+    }
+
+    /// Fill the message list from the folders shown, or only those of `account`.
+    fn fetch_emails(&mut self, account: Option<usize>, ctx: &mut Context) {
+        let folders = self.folders(ctx);
+        if account.is_none() {
+            if folders.len() > 1 {
+                self.widgets.email.clear_listings();
+            }
+            // Including other accounts' fetches, for the folders shown before
+            ctx.workers.cancel(&LoadType::FetchEmails);
+            ctx.workers.cancel(&LoadType::Search);
+            ctx.workers.cancel(&LoadType::FetchMore);
+        }
+        for (a, inbox) in folders {
+            if account.is_none_or(|account| account == a) {
+                let n = ctx.config.page_size;
+                ctx.workers
+                    .send(a, LoadType::FetchEmails, Command::FetchEmails { inbox, n });
+            }
+        }
+    }
+
+    fn load(&mut self, load: LoadType, ctx: &mut Context) {
+        let (account, cmd) = match load {
+            LoadType::FetchEmails => return self.fetch_emails(None, ctx),
+            LoadType::FetchMore => match self.widgets.email.next_page() {
+                Some((listing, before_uid)) => (
+                    listing.account,
+                    Command::FetchMore {
+                        inbox: listing.inbox.to_owned(),
+                        uid_validity: listing.uid_validity,
+                        criteria: listing.criteria.to_owned(),
+                        before_uid,
+                        n: ctx.config.page_size as usize,
+                    },
+                ),
+                None => return,
+            },
+            LoadType::FetchPreview => match self.widgets.email.selected() {
+                Some(entry) => (
+                    entry.account,
+                    Command::FetchPreview {
+                        inbox: entry.inbox.to_owned(),
+                        uid: entry.uid,
+                        uid_validity: entry.uid_validity,
+                    },
+                ),
+                None => return,
+            },
+            LoadType::SetFlag(flag, add) => {
+                for (account, inbox, uid_validity, uids) in self.targets() {
+                    let cmd = Command::SetFlag {
+                        inbox,
+                        uid_validity,
+                        uids,
+                        flag,
+                        add,
+                    };
+                    ctx.workers.send(account, load.clone(), cmd);
+                }
+                return;
+            }
+            LoadType::Expunge => {
+                for (account, inbox, ..) in self.targets() {
+                    ctx.workers
+                        .send(account, load.clone(), Command::Expunge { inbox });
+                }
+                return;
+            }
+            LoadType::Transfer(op, ref dest) => {
+                // The mailbox was picked from the first account's, which others may not have
+                let sidebar = &self.widgets.sidebar;
+                let has = |account| {
+                    let names = sidebar.names(account);
+                    (names.is_empty() || names.contains(dest)).then(|| dest.to_owned())
+                };
+                let skipped = self.transfer(load.clone(), op, has, ctx);
+                if !skipped.is_empty() {
+                    let names: Vec<&str> = skipped
+                        .iter()
+                        .map(|a| ctx.accounts[*a].name.as_str())
+                        .collect();
+                    ctx.notify(format!(
+                        "Skipped messages of {}, which has no mailbox \"{}\"",
+                        names.join(", "),
+                        dest
+                    ));
+                }
+                return;
+            }
+            LoadType::Archive | LoadType::Trash => {
+                let special_use = match load {
+                    LoadType::Archive => SpecialUse::Archive,
+                    _ => SpecialUse::Trash,
+                };
+                // Looked up front, `transfer` needing the context to send
+                let dests: Vec<Option<String>> = ctx
+                    .accounts
+                    .iter()
+                    .enumerate()
+                    .map(|(account, a)| {
+                        let configured = match special_use {
+                            SpecialUse::Archive => &a.archive_folder,
+                            _ => &a.trash_folder,
                         };
-                        self.widgets.email.set_entries(subs);
+                        configured
+                            .to_owned()
+                            .or(self.widgets.sidebar.special_use(account, special_use))
+                    })
+                    .collect();
+                let dest = |account: usize| dests[account].to_owned();
+                let skipped = self.transfer(load.clone(), Transfer::Move, dest, ctx);
+                for account in skipped {
+                    let e = match special_use {
+                        SpecialUse::Archive => "No archive mailbox, set archive_folder in config",
+                        _ => "No trash mailbox, set trash_folder in config",
+                    };
+                    ctx.show_error(self.account_error(account, e.to_owned(), ctx));
+                }
+                return;
+            }
+            LoadType::Search => {
+                let criteria = match query::to_criteria(self.widgets.search.input.as_str()) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        ctx.show_error(e);
+                        return;
                     }
-                    LoadType::FetchPreview => {
-                        let session = match &mut ctx.session {
-                            Some(s) => s,
-                            None => {
-                                ctx.show_error("Not logged in");
-                                continue;
-                            }
-                        };
-                        ctx.mode = Mode::Focus(Focusable::Preview);
-                        let inbox_idx = self
-                            .widgets
-                            .sidebar
-                            .table
-                            .state
-                            .selected()
-                            .unwrap_or_default();
-                        let inbox = self
-                            .widgets
-                            .sidebar
-                            .table
-                            .items
-                            .get(inbox_idx)
-                            .map(|i| i.to_owned())
-                            .unwrap_or("INBOX".to_owned());
-                        let text = match email::get_html(
-                            session,
+                };
+                // Both fill the message list, so only the latest may finish
+                ctx.workers.cancel(&LoadType::FetchEmails);
+                ctx.workers.cancel(&LoadType::FetchMore);
+                let folders = self.folders(ctx);
+                if folders.len() > 1 {
+                    self.widgets.email.clear_listings();
+                }
+                for (account, inbox) in folders {
+                    let cmd = Command::Search {
+                        inbox,
+                        criteria: criteria.to_owned(),
+                        n: ctx.config.page_size as usize,
+                    };
+                    ctx.workers.send(account, LoadType::Search, cmd);
+                }
+                return;
+            }
+            LoadType::FetchInboxes => {
+                for account in 0..ctx.accounts.len() {
+                    ctx.workers
+                        .send(account, LoadType::FetchInboxes, Command::FetchInboxes);
+                }
+                return;
+            }
+            LoadType::Command(line) => return self.run_command(&line, ctx),
+            LoadType::Reply(_, ref from) => {
+                let message = match from {
+                    Focusable::Preview => self.widgets.preview.message().cloned(),
+                    _ => self
+                        .widgets
+                        .email
+                        .selected()
+                        .map(|e| (e.account, e.inbox.to_owned(), e.uid, e.uid_validity)),
+                };
+                match message {
+                    Some((account, inbox, uid, uid_validity)) if self.can_send(account, ctx) => (
+                        account,
+                        Command::FetchMessage {
                             inbox,
-                            self.widgets
-                                .email
-                                .table
-                                .state
-                                .selected()
-                                .unwrap_or_default() as u32,
-                        ) {
-                            Ok(body) => body,
-                            Err(e) => {
-                                ctx.show_error(e);
-                                continue;
-                            }
-                        }
-                        .unwrap_or_default();
-                        self.widgets.preview.set_content(text);
-                    }
-                    LoadType::FetchInboxes => {
-                        let session = match &mut ctx.session {
-                            Some(s) => s,
-                            None => {
-                                ctx.show_error("Not logged in");
-                                continue;
-                            }
-                        };
-                        ctx.mode = Mode::Focus(Focusable::Emails);
-                        let inboxes = match email::list_inboxes(session) {
-                            Ok(body) => body,
-                            Err(e) => {
-                                ctx.show_error(e);
-                                continue;
-                            }
-                        };
-                        ctx.mode = Mode::Loading(LoadType::FetchEmails);
-                        self.widgets.sidebar.set_inboxes(inboxes);
-                    }
-                    LoadType::Login => {
-                        ctx.mode = Mode::Focus(Focusable::Emails);
-                        if let Some(s) = &mut ctx.session {
-                            if let Err(e) = s.logout() {
-                                ctx.show_error(e);
-                                ctx.session = None;
-                                continue;
-                            }
-                        }
-                        let s = match new_session(ctx.config.clone()) {
-                            Ok(s) => s,
-                            Err(e) => {
-                                ctx.show_error(e.to_string());
-                                continue;
-                            }
-                        };
-                        ctx.session = Some(s);
-                        ctx.mode = Mode::Loading(LoadType::FetchInboxes);
-                    }
+                            uid,
+                            uid_validity,
+                        },
+                    ),
+                    _ => return,
                 }
-                continue;
             }
+            // Sent straight from the editor, there being nothing to gather
+            LoadType::Send => return,
+            LoadType::Login => {
+                for (account, a) in ctx.accounts.iter().enumerate() {
+                    ctx.workers.send(
+                        account,
+                        LoadType::Login,
+                        Command::Login(Box::new(a.clone())),
+                    );
+                }
+                return;
+            }
+        };
+        ctx.workers.send(account, load, cmd);
+    }
 
-            let evt = event::read()?;
-            self.on(evt.clone(), &mut ctx);
-            self.widgets.on(evt, &mut ctx);
+    /// Run `line` from the `:` prompt, with whatever had focus before focused again.
+    fn run_command(&mut self, line: &str, ctx: &mut Context) {
+        let cmd = match command::parse(line) {
+            Ok(cmd) => cmd,
+            Err(e) => return ctx.show_error(e),
+        };
+        match cmd {
+            UserCommand::Action(action) => {
+                self.widgets.act(action, ctx);
+                self.act(action, ctx);
+            }
+            UserCommand::Transfer(op, dest) => {
+                // Fine if any account of the messages has it, the others' are skipped
+                let names: Vec<Vec<String>> = self
+                    .targets()
+                    .iter()
+                    .map(|t| self.widgets.sidebar.names(t.0))
+                    .collect();
+                if names.is_empty() || names.iter().any(|n| n.is_empty() || n.contains(&dest)) {
+                    ctx.mode = Mode::Loading(LoadType::Transfer(op, dest));
+                } else {
+                    ctx.show_error(format!("No mailbox \"{}\"", dest));
+                }
+            }
+            UserCommand::Search(query) => {
+                self.widgets.search.open(SearchKind::Server);
+                self.widgets.search.input.set(&query);
+                ctx.mode = Mode::Loading(LoadType::Search);
+            }
+            UserCommand::Filter(query) => {
+                self.widgets.search.open(SearchKind::Filter);
+                self.widgets.search.input.set(&query);
+                self.widgets.email.set_filter(&query);
+            }
+            UserCommand::Mailbox(name) => {
+                let folder = Folder::Mailbox(self.widgets.sidebar.account(), name.to_owned());
+                match self.widgets.sidebar.select(&folder) {
+                    true => ctx.mode = Mode::Loading(LoadType::FetchEmails),
+                    false => ctx.show_error(format!("No mailbox \"{}\"", name)),
+                }
+            }
+            UserCommand::Account(name) => {
+                let account = ctx.accounts.iter().position(|a| a.name == name);
+                match account.is_some_and(|a| self.widgets.sidebar.select_account(a)) {
+                    true => ctx.mode = Mode::Loading(LoadType::FetchEmails),
+                    false => ctx.show_error(format!("No account \"{}\"", name)),
+                }
+            }
+            UserCommand::Set(Setting::Sort(sort)) => self.widgets.email.set_sort(sort),
+            UserCommand::Set(Setting::PageSize(n)) => ctx.config.page_size = n,
         }
+    }
+
+    /// Start watching `account` and list its mailboxes, once it has a session.
+    fn logged_in(&mut self, account: usize, ctx: &mut Context) {
+        ctx.watchers[account] = Some(Watcher::spawn(ctx.accounts[account].clone()));
+        ctx.workers
+            .send(account, LoadType::FetchInboxes, Command::FetchInboxes);
+    }
 
-        if let Some(session) = &mut ctx.session {
-            session.logout()?;
+    fn on_worker(&mut self, account: usize, load: LoadType, evt: WorkerEvent, ctx: &mut Context) {
+        match evt {
+            WorkerEvent::LoggedIn => self.logged_in(account, ctx),
+            WorkerEvent::Inboxes(inboxes) => {
+                self.widgets.sidebar.set_inboxes(account, inboxes);
+                self.fetch_emails(Some(account), ctx);
+            }
+            WorkerEvent::Emails { entries, listing } => {
+                // A listing of a folder no longer shown, that finished before it was cancelled
+                let folders = self.folders(ctx);
+                if !folders.contains(&(account, listing.inbox.to_owned())) {
+                    return;
+                }
+                if let Some(watcher) = &ctx.watchers[account] {
+                    let newest = entries.iter().map(|e| e.uid).max().unwrap_or_default();
+                    watcher.watch(listing.inbox.to_owned(), listing.uid_validity, newest);
+                }
+                match folders.len() {
+                    1 => self.widgets.email.set_entries(entries, listing),
+                    _ => self.widgets.email.merge(entries, listing),
+                }
+            }
+            WorkerEvent::MoreEmails(entries) => self.widgets.email.append(entries),
+            WorkerEvent::Preview {
+                inbox,
+                uid,
+                uid_validity,
+                html,
+            } => {
+                // Fetching the body marks the message as read on the server, if there is one
+                if matches!(ctx.workers.state(account), ConnectionState::Online) {
+                    self.widgets
+                        .email
+                        .update(account, &inbox, uid, |e| e.flags.seen = true);
+                }
+                let message = (account, inbox, uid, uid_validity);
+                self.widgets.preview.set_content(message, html);
+            }
+            WorkerEvent::Flags { inbox, flags } => {
+                for (uid, flags) in flags {
+                    self.widgets
+                        .email
+                        .update(account, &inbox, uid, |e| e.flags = flags);
+                }
+            }
+            WorkerEvent::Expunged { inbox } => {
+                self.widgets.email.remove_deleted(account, &inbox);
+                ctx.notify(format!("Expunged deleted messages from {}", inbox));
+            }
+            WorkerEvent::Transferred {
+                inbox,
+                uids,
+                op,
+                dest,
+            } => {
+                let verb = match op {
+                    Transfer::Move => "Moved",
+                    Transfer::Copy => "Copied",
+                };
+                ctx.notify(match uids.len() {
+                    1 => format!("{} 1 message to {}", verb, dest),
+                    n => format!("{} {} messages to {}", verb, n, dest),
+                });
+                match op {
+                    Transfer::Move => self.widgets.email.remove(account, &inbox, &uids),
+                    Transfer::Copy => self.widgets.email.unmark_all(),
+                }
+            }
+            WorkerEvent::Message { inbox, uid, raw } => {
+                // Fetching the message marks it as read on the server, if there is one
+                if matches!(ctx.workers.state(account), ConnectionState::Online) {
+                    self.widgets
+                        .email
+                        .update(account, &inbox, uid, |e| e.flags.seen = true);
+                }
+                let LoadType::Reply(kind, _) = load else {
+                    return;
+                };
+                match Draft::respond(kind, &raw, account, &ctx.accounts[account]) {
+                    Ok(draft) => self.composing = Some(draft),
+                    Err(e) => ctx.show_error(e),
+                }
+            }
+            WorkerEvent::Sent { saved } => {
+                self.unsent = None;
+                match saved {
+                    None => ctx.notify("Message sent"),
+                    Some(Ok(folder)) => ctx.notify(format!("Message sent and saved to {}", folder)),
+                    Some(Err(e)) => {
+                        let e = format!("Message sent, but keeping a copy failed: {}", e);
+                        ctx.show_error(self.account_error(account, e, ctx));
+                    }
+                }
+            }
+            WorkerEvent::Progress { .. } => {}
+            // The first login failed to reach the server, and the worker got there retrying
+            WorkerEvent::State(ConnectionState::Online) if ctx.watchers[account].is_none() => {
+                let msg = self.account_error(account, "Connected".to_owned(), ctx);
+                ctx.notify(msg);
+                self.logged_in(account, ctx);
+            }
+            // Whatever changed meanwhile is caught up on like when the folder was opened
+            WorkerEvent::State(ConnectionState::Online) => {
+                let msg = self.account_error(account, "Reconnected".to_owned(), ctx);
+                ctx.notify(msg);
+                self.fetch_emails(Some(account), ctx);
+            }
+            WorkerEvent::State(ConnectionState::Reconnecting) => {
+                let msg = "Connection lost, reconnecting".to_owned();
+                let msg = self.account_error(account, msg, ctx);
+                ctx.notify(msg);
+            }
+            WorkerEvent::State(_) => {}
+            WorkerEvent::Error(e) => {
+                // A failed login leaves nothing else to do, so drop anything queued behind it
+                if load == LoadType::Login {
+                    ctx.workers.cancel_account(account);
+                }
+                // Otherwise the next tick would ask for the same page again
+                if load == LoadType::FetchMore {
+                    self.widgets.email.stop_paging();
+                }
+                let e = match load {
+                    LoadType::Send => {
+                        format!("Message not sent: {}. Composing again reopens it", e)
+                    }
+                    _ => e.to_string(),
+                };
+                ctx.show_error(self.account_error(account, e, ctx));
+            }
         }
+    }
 
-        Ok(())
+    fn on_watch(&mut self, account: usize, evt: WatchEvent, ctx: &mut Context) {
+        match evt {
+            WatchEvent::Arrived {
+                inbox,
+                mut entries,
+                exists,
+            } => {
+                entries.iter_mut().for_each(|e| e.account = account);
+                self.widgets.email.insert(account, &inbox, entries, exists);
+            }
+            WatchEvent::Vanished {
+                inbox,
+                uids,
+                exists,
+            } => self.widgets.email.vanished(account, &inbox, &uids, exists),
+            WatchEvent::Error(e) => {
+                let e = format!("Watching for new mail: {}", e);
+                ctx.show_error(self.account_error(account, e, ctx));
+            }
+        }
     }
 
-    fn on(&mut self, e: Event, ctx: &mut Context) {
-        if let Event::Key(KeyEvent {
-            code,
-            kind: KeyEventKind::Press,
-            ..
-        }) = e
-        {
-            match code {
-                KeyCode::Char('q') => {
-                    self.should_quit = true;
-                }
-                KeyCode::Char('l') | KeyCode::Tab => {
-                    if let Mode::Focus(f) = ctx.mode.clone() {
-                        ctx.mode = Mode::Focus(match f {
-                            Focusable::Search => Focusable::Emails,
-                            Focusable::Sidebar => Focusable::Emails,
-                            Focusable::Emails => Focusable::Preview,
-                            Focusable::Preview => Focusable::Sidebar,
-                        });
+    /// `e` prefixed with the account it happened in, if there is more than one. Also used for
+    /// messages that aren't errors.
+    fn account_error(&self, account: usize, e: String, ctx: &Context) -> String {
+        match ctx.accounts.len() {
+            1 => e,
+            _ => format!("{}: {}", ctx.accounts[account].name, e),
+        }
+    }
+
+    /// Actions that work whatever has focus, the widgets having had their go.
+    fn act(&mut self, action: Action, ctx: &mut Context) {
+        match action {
+            Action::Quit => {
+                self.should_quit = true;
+            }
+            // Closes the popups instead
+            Action::Cancel
+                if !matches!(
+                    ctx.mode,
+                    Mode::Focus(Focusable::Picker(_) | Focusable::Log | Focusable::Help)
+                        | Mode::Error(_)
+                ) =>
+            {
+                ctx.workers.cancel_all();
+            }
+            Action::Help => {
+                if let Mode::Focus(f) = ctx.mode.clone() {
+                    if f != Focusable::Help {
+                        let global = ctx.keymap.describe(Scope::Global, &GLOBAL_ACTIONS);
+                        let mut sections = vec![("Everywhere", global)];
+                        sections.extend(self.widgets.help(&ctx.mode, &ctx.keymap));
+                        self.widgets.help.open(f, sections);
+                        ctx.mode = Mode::Focus(Focusable::Help);
                     }
                 }
-                KeyCode::Char('h') | KeyCode::BackTab => {
-                    if let Mode::Focus(f) = ctx.mode.clone() {
-                        ctx.mode = Mode::Focus(match f {
-                            Focusable::Search => Focusable::Emails,
-                            Focusable::Emails => Focusable::Sidebar,
-                            Focusable::Preview => Focusable::Emails,
-                            Focusable::Sidebar => Focusable::Preview,
-                        });
+            }
+            Action::Log => {
+                if let Mode::Focus(f) = ctx.mode.clone() {
+                    if !matches!(f, Focusable::Picker(_) | Focusable::Log | Focusable::Help) {
+                        self.widgets.notifications.open_log();
+                        ctx.mode = Mode::Focus(Focusable::Log);
+                    }
+                }
+            }
+            Action::NextPane => {
+                if let Mode::Focus(f) = ctx.mode.clone() {
+                    ctx.mode = Mode::Focus(match f {
+                        Focusable::Search => Focusable::Emails,
+                        Focusable::Sidebar => Focusable::Emails,
+                        Focusable::Emails => Focusable::Preview,
+                        Focusable::Preview => Focusable::Sidebar,
+                        Focusable::Picker(op) => Focusable::Picker(op),
+                        Focusable::Log => Focusable::Log,
+                        Focusable::Help => Focusable::Help,
+                        Focusable::Command => Focusable::Command,
+                    });
+                }
+            }
+            Action::PrevPane => {
+                if let Mode::Focus(f) = ctx.mode.clone() {
+                    ctx.mode = Mode::Focus(match f {
+                        Focusable::Search => Focusable::Emails,
+                        Focusable::Emails => Focusable::Sidebar,
+                        Focusable::Preview => Focusable::Emails,
+                        Focusable::Sidebar => Focusable::Preview,
+                        Focusable::Picker(op) => Focusable::Picker(op),
+                        Focusable::Log => Focusable::Log,
+                        Focusable::Help => Focusable::Help,
+                        Focusable::Command => Focusable::Command,
+                    });
+                }
+            }
+            Action::Command => {
+                if let Mode::Focus(f) = ctx.mode.clone() {
+                    if !matches!(
+                        f,
+                        Focusable::Picker(_)
+                            | Focusable::Log
+                            | Focusable::Help
+                            | Focusable::Command
+                    ) {
+                        let account = self.widgets.sidebar.account();
+                        let accounts = ctx.accounts.iter().map(|a| a.name.to_owned()).collect();
+                        self.widgets
+                            .command
+                            .open(f, self.widgets.sidebar.names(account), accounts);
+                        ctx.mode = Mode::Focus(Focusable::Command);
                     }
                 }
-                KeyCode::Char('/') => {
-                    if let Mode::Focus(f) = ctx.mode.clone() {
-                        if f != Focusable::Search {
-                            ctx.mode = Mode::Focus(Focusable::Search);
-                        }
+            }
+            Action::Compose => {
+                if let Mode::Focus(f) = ctx.mode.clone() {
+                    if !matches!(
+                        f,
+                        Focusable::Picker(_)
+                            | Focusable::Log
+                            | Focusable::Help
+                            | Focusable::Command
+                    ) {
+                        self.compose(ctx);
+                    }
+                }
+            }
+            Action::Filter | Action::Search => {
+                if let Mode::Focus(f) = ctx.mode.clone() {
+                    if !matches!(
+                        f,
+                        Focusable::Search
+                            | Focusable::Picker(_)
+                            | Focusable::Log
+                            | Focusable::Help
+                            | Focusable::Command
+                    ) {
+                        self.widgets.search.open(match action {
+                            Action::Filter => SearchKind::Filter,
+                            _ => SearchKind::Server,
+                        });
+                        ctx.mode = Mode::Focus(Focusable::Search);
                     }
                 }
-                _ => {}
             }
+            _ => {}
         }
     }
 }
@@ -0,0 +1,326 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::DateTime;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Account, APP_NAME},
+    email::Inbox,
+    widget::emails::{EmailEntry, Flags, Listing},
+};
+
+/// On-disk copy of everything fetched so far, so the app starts instantly and keeps working
+/// read-only without a connection. Laid out as `<cache dir>/<account>/<mailbox>/<UIDVALIDITY>/`,
+/// each holding the envelopes and flags of the listed messages in `index.toml` and the messages
+/// previewed so far in `cur/<uid>`, Maildir style. Writes are best effort: a cache that can't be
+/// written is just slower to start next time.
+pub struct Cache {
+    root: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Index {
+    /// HIGHESTMODSEQ the entries are up to date with, on servers with CONDSTORE
+    highest_modseq: Option<u64>,
+    /// Keyed by UID as a string, TOML tables allowing nothing else
+    entries: BTreeMap<String, Record>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    from: String,
+    subject: String,
+    /// RFC 3339
+    date: Option<String>,
+    flags: Flags,
+    attachment: bool,
+    #[serde(default)]
+    unreadable: bool,
+}
+
+/// How far the cached copy of a mailbox goes, to bring it up to date from. The cache always holds
+/// every message from the lowest UID up.
+pub struct Synced {
+    /// Ascending
+    pub uids: Vec<u32>,
+    pub highest_modseq: Option<u64>,
+}
+
+/// What happened to a mailbox since it was last [`Synced`].
+pub struct Changes {
+    pub arrived: Vec<EmailEntry>,
+    pub vanished: Vec<u32>,
+    pub flags: Vec<(u32, Flags)>,
+    pub highest_modseq: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Inboxes {
+    inboxes: Vec<Inbox>,
+}
+
+impl Cache {
+    /// The cache of `account`, if the platform has a cache directory.
+    pub fn open(account: &Account) -> Option<Cache> {
+        let dirs = ProjectDirs::from("rs", "", APP_NAME)?;
+        let account = format!("{}@{}", account.username, account.imap_server);
+        Some(Cache {
+            root: dirs.cache_dir().join(escape(&account)),
+        })
+    }
+
+    pub fn inboxes(&self) -> Option<Vec<Inbox>> {
+        read::<Inboxes>(&self.root.join("inboxes.toml")).map(|i| i.inboxes)
+    }
+
+    pub fn store_inboxes(&self, inboxes: &[Inbox]) {
+        let inboxes = Inboxes {
+            inboxes: inboxes.to_vec(),
+        };
+        write(&self.root.join("inboxes.toml"), &inboxes);
+    }
+
+    /// Every cached message of `inbox`, newest first.
+    pub fn listing(&self, inbox: &str) -> Option<(Vec<EmailEntry>, Listing)> {
+        let uid_validity = self.uid_validity(inbox)?;
+        let index = read::<Index>(&self.dir(inbox, uid_validity).join("index.toml"))?;
+        let mut entries: Vec<EmailEntry> = index
+            .entries
+            .iter()
+            .filter_map(|(uid, r)| Some(r.to_entry(inbox, uid.parse().ok()?, uid_validity)))
+            .collect();
+        entries.sort_unstable_by_key(|e| Reverse(e.uid));
+        let listing = Listing {
+            account: 0,
+            inbox: inbox.to_owned(),
+            uid_validity,
+            total: entries.len(),
+            criteria: None,
+        };
+        Some((entries, listing))
+    }
+
+    pub fn synced(&self, inbox: &str, uid_validity: u32) -> Option<Synced> {
+        let index = read::<Index>(&self.dir(inbox, uid_validity).join("index.toml"))?;
+        let mut uids: Vec<u32> = index
+            .entries
+            .keys()
+            .filter_map(|u| u.parse().ok())
+            .collect();
+        uids.sort_unstable();
+        Some(Synced {
+            uids,
+            highest_modseq: index.highest_modseq,
+        })
+    }
+
+    /// Start the cache of `inbox` over with `entries`, the newest messages in it. Cached bodies
+    /// are kept unless the mailbox's UIDVALIDITY changed.
+    pub fn replace(
+        &self,
+        inbox: &str,
+        uid_validity: u32,
+        entries: &[EmailEntry],
+        highest_modseq: Option<u64>,
+    ) {
+        if self.uid_validity(inbox) != Some(uid_validity) {
+            let _ = fs::remove_dir_all(self.root.join(escape(inbox)));
+        }
+        let index = Index {
+            highest_modseq,
+            entries: entries
+                .iter()
+                .map(|e| (e.uid.to_string(), Record::from(e)))
+                .collect(),
+        };
+        write(&self.dir(inbox, uid_validity).join("index.toml"), &index);
+    }
+
+    /// Add a page of older messages, following on from the cached ones.
+    pub fn append(&self, entries: &[EmailEntry]) {
+        let Some(first) = entries.first() else {
+            return;
+        };
+        self.update_index(&first.inbox, first.uid_validity, |index| {
+            for e in entries {
+                index.entries.insert(e.uid.to_string(), Record::from(e));
+            }
+        });
+    }
+
+    pub fn apply(&self, inbox: &str, uid_validity: u32, changes: &Changes) {
+        self.update_index(inbox, uid_validity, |index| {
+            for uid in &changes.vanished {
+                index.entries.remove(&uid.to_string());
+            }
+            for (uid, flags) in &changes.flags {
+                if let Some(r) = index.entries.get_mut(&uid.to_string()) {
+                    r.flags = *flags;
+                }
+            }
+            for e in &changes.arrived {
+                index.entries.insert(e.uid.to_string(), Record::from(e));
+            }
+            index.highest_modseq = changes.highest_modseq;
+        });
+        for uid in &changes.vanished {
+            let _ = fs::remove_file(self.body_path(inbox, uid_validity, *uid));
+        }
+    }
+
+    pub fn update_flags(&self, inbox: &str, uid_validity: u32, flags: &[(u32, Flags)]) {
+        self.update_index(inbox, uid_validity, |index| {
+            for (uid, flags) in flags {
+                if let Some(r) = index.entries.get_mut(&uid.to_string()) {
+                    r.flags = *flags;
+                }
+            }
+        });
+    }
+
+    pub fn mark_seen(&self, inbox: &str, uid_validity: u32, uid: u32) {
+        self.update_index(inbox, uid_validity, |index| {
+            if let Some(r) = index.entries.get_mut(&uid.to_string()) {
+                r.flags.seen = true;
+            }
+        });
+    }
+
+    pub fn remove(&self, inbox: &str, uid_validity: u32, uids: &[u32]) {
+        self.update_index(inbox, uid_validity, |index| {
+            for uid in uids {
+                index.entries.remove(&uid.to_string());
+            }
+        });
+        for uid in uids {
+            let _ = fs::remove_file(self.body_path(inbox, uid_validity, *uid));
+        }
+    }
+
+    /// Drop the messages of `inbox` marked `\Deleted`, after an expunge.
+    pub fn remove_deleted(&self, inbox: &str) {
+        let Some(uid_validity) = self.uid_validity(inbox) else {
+            return;
+        };
+        let path = self.dir(inbox, uid_validity).join("index.toml");
+        let Some(index) = read::<Index>(&path) else {
+            return;
+        };
+        let deleted: Vec<u32> = index
+            .entries
+            .iter()
+            .filter(|(_, r)| r.flags.deleted)
+            .filter_map(|(uid, _)| uid.parse().ok())
+            .collect();
+        self.remove(inbox, uid_validity, &deleted);
+    }
+
+    /// The raw message, if it was previewed before.
+    pub fn body(&self, inbox: &str, uid_validity: u32, uid: u32) -> Option<Vec<u8>> {
+        fs::read(self.body_path(inbox, uid_validity, uid)).ok()
+    }
+
+    pub fn store_body(&self, inbox: &str, uid_validity: u32, uid: u32, body: &[u8]) {
+        let path = self.body_path(inbox, uid_validity, uid);
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(path, body);
+    }
+
+    /// UIDVALIDITY of the cached copy of `inbox`, there being at most one.
+    fn uid_validity(&self, inbox: &str) -> Option<u32> {
+        fs::read_dir(self.root.join(escape(inbox)))
+            .ok()?
+            .filter_map(|d| d.ok()?.file_name().to_str()?.parse().ok())
+            .next()
+    }
+
+    fn dir(&self, inbox: &str, uid_validity: u32) -> PathBuf {
+        self.root.join(escape(inbox)).join(uid_validity.to_string())
+    }
+
+    fn body_path(&self, inbox: &str, uid_validity: u32, uid: u32) -> PathBuf {
+        self.dir(inbox, uid_validity)
+            .join("cur")
+            .join(uid.to_string())
+    }
+
+    /// Apply `f` to the index of `inbox`, if it is cached under `uid_validity`.
+    fn update_index<F: FnOnce(&mut Index)>(&self, inbox: &str, uid_validity: u32, f: F) {
+        let path = self.dir(inbox, uid_validity).join("index.toml");
+        let Some(mut index) = read::<Index>(&path) else {
+            return;
+        };
+        f(&mut index);
+        write(&path, &index);
+    }
+}
+
+impl Record {
+    fn to_entry(&self, inbox: &str, uid: u32, uid_validity: u32) -> EmailEntry {
+        EmailEntry {
+            account: 0,
+            inbox: inbox.to_owned(),
+            uid,
+            uid_validity,
+            from: self.from.to_owned(),
+            subject: self.subject.to_owned(),
+            date: self
+                .date
+                .as_ref()
+                .and_then(|d| DateTime::parse_from_rfc3339(d).ok()),
+            flags: self.flags,
+            attachment: self.attachment,
+            unreadable: self.unreadable,
+            marked: false,
+        }
+    }
+}
+
+impl From<&EmailEntry> for Record {
+    fn from(e: &EmailEntry) -> Self {
+        Record {
+            from: e.from.to_owned(),
+            subject: e.subject.to_owned(),
+            date: e.date.map(|d| d.to_rfc3339()),
+            flags: e.flags,
+            attachment: e.attachment,
+            unreadable: e.unreadable,
+        }
+    }
+}
+
+/// Make a mailbox or account name safe to use as a single path component.
+fn escape(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || "@-_ ".contains(c) => c.to_string(),
+            c => c
+                .to_string()
+                .bytes()
+                .map(|b| format!("%{:02X}", b))
+                .collect(),
+        })
+        .collect()
+}
+
+fn read<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    toml::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn write<T: Serialize>(path: &Path, value: &T) {
+    let Ok(s) = toml::to_string(value) else {
+        return;
+    };
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(path, s);
+}
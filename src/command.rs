@@ -0,0 +1,214 @@
+use crate::{email::Transfer, keymap::Action, widget::emails::Sort};
+
+/// Commands taking an argument, beside the actions which are commands of their own. `move`,
+/// `copy`, `search` and `filter` without an argument are the actions of the same name.
+const COMMANDS: [&str; 7] = [
+    "move", "copy", "search", "filter", "mailbox", "account", "set",
+];
+
+const SETTINGS: [&str; 2] = ["sort", "page_size"];
+
+/// A line typed at the `:` prompt.
+pub enum UserCommand {
+    /// What a key bound to the action would do
+    Action(Action),
+    Transfer(Transfer, String),
+    Search(String),
+    Filter(String),
+    /// Show a mailbox of the account of the shown one
+    Mailbox(String),
+    /// Show an account's INBOX, by name
+    Account(String),
+    Set(Setting),
+}
+
+pub enum Setting {
+    Sort(Sort),
+    PageSize(u32),
+}
+
+pub fn parse(line: &str) -> Result<UserCommand, String> {
+    let line = line.trim();
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (line, ""),
+    };
+    if arg.is_empty() {
+        return match Action::parse(name) {
+            Some(action) => Ok(UserCommand::Action(action)),
+            None if COMMANDS.contains(&name) => Err(format!("{} needs an argument", name)),
+            None => Err(format!("Unknown command \"{}\"", name)),
+        };
+    }
+    Ok(match name {
+        "move" => UserCommand::Transfer(Transfer::Move, unquote(arg)?),
+        "copy" => UserCommand::Transfer(Transfer::Copy, unquote(arg)?),
+        // Queries do their own quoting
+        "search" => UserCommand::Search(arg.to_owned()),
+        "filter" => UserCommand::Filter(arg.to_owned()),
+        "mailbox" => UserCommand::Mailbox(unquote(arg)?),
+        "account" => UserCommand::Account(unquote(arg)?),
+        "set" => UserCommand::Set(parse_setting(arg)?),
+        _ if Action::parse(name).is_some() => {
+            return Err(format!("{} takes no argument", name));
+        }
+        _ => return Err(format!("Unknown command \"{}\"", name)),
+    })
+}
+
+/// A name as typed, or the inside of it if it is double quoted, with `\"` and `\\` escaping a
+/// quote and a backslash. Names with spaces need no quotes, as the rest of the line is the name.
+fn unquote(arg: &str) -> Result<String, String> {
+    let Some(quoted) = arg.strip_prefix('"') else {
+        return Ok(arg.to_owned());
+    };
+    let mut name = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => name.extend(chars.next()),
+            '"' if chars.as_str().is_empty() => return Ok(name),
+            '"' => {
+                return Err(format!(
+                    "Unexpected \"{}\" after the quoted name",
+                    chars.as_str()
+                ))
+            }
+            c => name.push(c),
+        }
+    }
+    Err(format!("Missing closing quote in {}", arg))
+}
+
+fn parse_setting(arg: &str) -> Result<Setting, String> {
+    let (name, value) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+    let value = value.trim();
+    match name {
+        "sort" => Sort::parse(value).map(Setting::Sort).ok_or(format!(
+            "Can't sort by \"{}\", only by {}",
+            value,
+            Sort::names().collect::<Vec<_>>().join(", ")
+        )),
+        "page_size" => match value.parse() {
+            Ok(n) if n > 0 => Ok(Setting::PageSize(n)),
+            _ => Err(format!(
+                "page_size must be a positive number, not \"{}\"",
+                value
+            )),
+        },
+        _ => Err(format!(
+            "Unknown setting \"{}\", expected one of: {}",
+            name,
+            SETTINGS.join(", ")
+        )),
+    }
+}
+
+/// Lines `line` could be completed to, with the mailboxes of the shown account and the names of
+/// the accounts to choose from.
+pub fn complete(line: &str, mailboxes: &[String], accounts: &[String]) -> Vec<String> {
+    let Some((name, arg)) = line.split_once(' ') else {
+        let names = COMMANDS.into_iter().chain(Action::names());
+        let mut names: Vec<String> = names
+            .filter(|n| n.starts_with(line))
+            .map(|n| n.to_owned())
+            .collect();
+        names.sort();
+        names.dedup();
+        return names;
+    };
+    let (prefix, arg, candidates): (String, &str, Vec<&str>) = match name {
+        "move" | "copy" | "mailbox" => (format!("{} ", name), arg, strs(mailboxes)),
+        "account" => (format!("{} ", name), arg, strs(accounts)),
+        "set" => match arg.split_once(' ') {
+            None => (format!("{} ", name), arg, SETTINGS.to_vec()),
+            Some(("sort", value)) => ("set sort ".to_owned(), value, Sort::names().collect()),
+            Some(_) => return vec![],
+        },
+        _ => return vec![],
+    };
+    candidates
+        .into_iter()
+        .filter(|c| c.starts_with(arg))
+        .map(|c| format!("{}{}", prefix, c))
+        .collect()
+}
+
+fn strs(strings: &[String]) -> Vec<&str> {
+    strings.iter().map(String::as_str).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(line: &str) -> String {
+        match parse(line) {
+            Ok(UserCommand::Transfer(_, name) | UserCommand::Mailbox(name)) => name,
+            Ok(UserCommand::Account(name)) => name,
+            _ => panic!("{} didn't parse to a name", line),
+        }
+    }
+
+    #[test]
+    fn actions_take_no_argument() {
+        assert!(matches!(
+            parse(" quit "),
+            Ok(UserCommand::Action(Action::Quit))
+        ));
+        assert!(matches!(
+            parse("move"),
+            Ok(UserCommand::Action(Action::Move))
+        ));
+        assert!(parse("quit now").is_err());
+        assert!(parse("account").is_err());
+        assert!(parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn names_run_to_the_end_of_the_line() {
+        assert_eq!(name("move Old Stuff "), "Old Stuff");
+        assert_eq!(name("mailbox INBOX/Work"), "INBOX/Work");
+        assert_eq!(name("account  work"), "work");
+    }
+
+    #[test]
+    fn quoted_names_are_unescaped() {
+        assert_eq!(name(r#"copy "Old Stuff""#), "Old Stuff");
+        assert_eq!(name(r#"mailbox "say \"hi\"""#), r#"say "hi""#);
+        assert_eq!(name(r#"mailbox "back\\slash""#), r"back\slash");
+        assert_eq!(name(r#"move """#), "");
+        // Only a leading quote starts a quoted name
+        assert_eq!(name(r#"move It's "fine""#), r#"It's "fine""#);
+    }
+
+    #[test]
+    fn bad_quoting_is_an_error() {
+        assert!(parse(r#"move "Old Stuff"#).is_err());
+        assert!(parse(r#"move "Old" Stuff"#).is_err());
+        assert!(parse(r#"move "Old Stuff\""#).is_err());
+    }
+
+    #[test]
+    fn queries_are_left_as_typed() {
+        let Ok(UserCommand::Search(query)) = parse(r#"search subject:"a b" from:bob"#) else {
+            panic!("not a search");
+        };
+        assert_eq!(query, r#"subject:"a b" from:bob"#);
+    }
+
+    #[test]
+    fn parses_settings() {
+        assert!(matches!(
+            parse("set sort date"),
+            Ok(UserCommand::Set(Setting::Sort(Sort::Date)))
+        ));
+        assert!(matches!(
+            parse("set page_size 50"),
+            Ok(UserCommand::Set(Setting::PageSize(50)))
+        ));
+        assert!(parse("set page_size 0").is_err());
+        assert!(parse("set sort size").is_err());
+        assert!(parse("set colour red").is_err());
+    }
+}
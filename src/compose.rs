@@ -0,0 +1,617 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    mem,
+    path::PathBuf,
+    process,
+};
+
+use lettre::{
+    message::{
+        header::{self, ContentTransferEncoding, ContentType},
+        Attachment, Body, Mailbox, Mailboxes, MultiPart, SinglePart,
+    },
+    Message,
+};
+use mail_parser::{Addr, HeaderValue, MessageParser};
+
+use crate::{config::Account, error::Error};
+
+/// Headers that can be edited, in the order the template lists them.
+const HEADERS: [&str; 5] = ["From", "To", "Cc", "Bcc", "Subject"];
+
+/// Width quoted HTML is wrapped to, leaving room for the quote markers.
+const QUOTE_WIDTH: usize = 72;
+
+/// Ways of writing back about a message.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind {
+    /// To the sender, or whoever they asked replies to go to
+    Reply,
+    /// To the sender and everyone else the message went to
+    ReplyAll,
+    /// Quoted in the body
+    Forward,
+    /// As a message/rfc822 attachment, untouched
+    ForwardAttached,
+}
+
+/// A message being written, as headers above the body like in the file the editor is given.
+#[derive(Clone, Default)]
+pub struct Draft {
+    /// Index into the configured accounts, of the one sending it
+    pub account: usize,
+    pub from: String,
+    pub to: String,
+    pub cc: String,
+    pub bcc: String,
+    pub subject: String,
+    pub body: String,
+    /// Message-ID of the message replied to, and those of the thread before it, all without the
+    /// angle brackets
+    pub in_reply_to: Option<String>,
+    pub references: Vec<String>,
+    /// A message forwarded as an attachment, with its file name
+    pub attachment: Option<(String, Vec<u8>)>,
+    /// What was last saved in the editor, opened again in place of the fields since it may not
+    /// parse
+    edited: Option<String>,
+}
+
+impl Draft {
+    /// A new message from `a`, the account at index `account`.
+    pub fn new(account: usize, a: &Account) -> Draft {
+        Draft {
+            account,
+            from: a.from.to_owned().unwrap_or(a.username.to_owned()),
+            ..Draft::default()
+        }
+    }
+
+    /// A reply to or forward of `raw`, the original message, from `a`.
+    pub fn respond(
+        kind: ReplyKind,
+        raw: &[u8],
+        account: usize,
+        a: &Account,
+    ) -> Result<Draft, Error> {
+        let msg = MessageParser::new()
+            .parse(raw)
+            .ok_or(Error::Parse("the message".to_owned()))?;
+        let mut draft = Draft::new(account, a);
+        let subject = msg.subject().unwrap_or_default();
+        let author = msg.from().and_then(|f| f.first()).map(address);
+        let date = msg.date().map(|d| d.to_rfc822());
+        match kind {
+            ReplyKind::Reply | ReplyKind::ReplyAll => {
+                let (to, cc) = draft.recipients(&msg, kind, a);
+                draft.to = to;
+                draft.cc = cc;
+                draft.subject = prefixed("Re", &["re"], subject);
+                draft.in_reply_to = msg.message_id().map(|id| id.to_owned());
+                draft.references = ids(msg.references());
+                draft.references.extend(draft.in_reply_to.to_owned());
+                let attribution = match (date, author) {
+                    (Some(date), Some(author)) => format!("On {}, {} wrote:", date, author),
+                    (None, Some(author)) => format!("{} wrote:", author),
+                    _ => "Someone wrote:".to_owned(),
+                };
+                let quoted: Vec<String> = plain_text(&msg)
+                    .lines()
+                    .map(|l| match l.is_empty() {
+                        true => ">".to_owned(),
+                        false => format!("> {}", l),
+                    })
+                    .collect();
+                draft.body = format!("{}\n{}\n\n", attribution, quoted.join("\n"));
+            }
+            ReplyKind::Forward => {
+                draft.subject = prefixed("Fwd", &["fwd", "fw"], subject);
+                let mut body = vec!["".to_owned(), "".to_owned()];
+                body.push("---------- Forwarded message ----------".to_owned());
+                let fields = [
+                    ("From", author),
+                    ("Date", date),
+                    ("Subject", msg.subject().map(|s| s.to_owned())),
+                    ("To", msg.to().map(addresses)),
+                    ("Cc", msg.cc().map(addresses)),
+                ];
+                for (name, value) in fields {
+                    if let Some(value) = value {
+                        body.push(format!("{}: {}", name, value));
+                    }
+                }
+                body.push("".to_owned());
+                body.push(plain_text(&msg));
+                draft.body = body.join("\n");
+            }
+            ReplyKind::ForwardAttached => {
+                draft.subject = prefixed("Fwd", &["fwd", "fw"], subject);
+                let name: String = subject
+                    .chars()
+                    .filter(|c| c.is_alphanumeric() || " -_.,".contains(*c))
+                    .take(60)
+                    .collect();
+                let name = match name.trim() {
+                    "" => "message",
+                    name => name,
+                };
+                draft.attachment = Some((format!("{}.eml", name), raw.to_vec()));
+            }
+        }
+        Ok(draft)
+    }
+
+    /// To and Cc of a reply to `msg`, leaving out the addresses of `a`. Replies to our own
+    /// messages go to whoever they went to.
+    fn recipients(
+        &self,
+        msg: &mail_parser::Message,
+        kind: ReplyKind,
+        a: &Account,
+    ) -> (String, String) {
+        let from: Option<Mailbox> = self.from.parse().ok();
+        let ours: Vec<String> = from
+            .map(|m| m.email.to_string())
+            .into_iter()
+            .chain([a.username.to_owned()])
+            .map(|address| address.to_lowercase())
+            .collect();
+        let is_ours = |addr: &&Addr| {
+            addr.address()
+                .is_some_and(|s| ours.contains(&s.to_lowercase()))
+        };
+        let from_us = msg
+            .from()
+            .is_some_and(|f| f.iter().any(|addr| is_ours(&addr)));
+        let author = match (msg.reply_to(), from_us) {
+            (Some(reply_to), _) => Some(reply_to),
+            (None, true) => msg.to(),
+            (None, false) => msg.from(),
+        };
+        let author: Vec<&Addr> = author.into_iter().flat_map(|addrs| addrs.iter()).collect();
+        let mut to = author.clone();
+        let mut cc = vec![];
+        if kind == ReplyKind::ReplyAll {
+            to.extend(msg.to().into_iter().flat_map(|addrs| addrs.iter()));
+            cc.extend(msg.cc().into_iter().flat_map(|addrs| addrs.iter()));
+        }
+        to.retain(|addr| !is_ours(addr));
+        cc.retain(|addr| !is_ours(addr));
+        if to.is_empty() {
+            to = mem::take(&mut cc);
+        }
+        // A note to self is still answered to self
+        if to.is_empty() {
+            to = author;
+        }
+        let mut seen: Vec<String> = vec![];
+        let mut unique = |addrs: Vec<&Addr>| -> Vec<String> {
+            addrs
+                .into_iter()
+                .filter(|addr| {
+                    let key = addr.address().unwrap_or_default().to_lowercase();
+                    let new = !seen.contains(&key);
+                    seen.push(key);
+                    new
+                })
+                .map(address)
+                .collect()
+        };
+        let to = unique(to);
+        let cc = unique(cc);
+        (to.join(", "), cc.join(", "))
+    }
+
+    /// Whether it was written in the editor before, as opposed to only prefilled.
+    pub fn was_edited(&self) -> bool {
+        self.edited.is_some()
+    }
+
+    /// The text to open in the editor.
+    pub fn text(&self) -> String {
+        if let Some(text) = &self.edited {
+            return text.to_owned();
+        }
+        let values = [&self.from, &self.to, &self.cc, &self.bcc, &self.subject];
+        let mut text: String = HEADERS
+            .iter()
+            .zip(values)
+            .map(|(name, value)| format!("{}: {}\n", name, value))
+            .collect();
+        // Deleting the line drops the attachment
+        if let Some((name, _)) = &self.attachment {
+            text.push_str(&format!("Attachment: {}\n", name));
+        }
+        text.push('\n');
+        text.push_str(&self.body);
+        text
+    }
+
+    /// Take the headers and body from `text` as saved in the editor. The text is kept either way,
+    /// to be edited again if it doesn't parse or the message can't be sent.
+    pub fn update(&mut self, text: String) -> Result<(), String> {
+        let mut lines = text.lines();
+        let mut headers: Vec<(String, String)> = vec![];
+        for line in lines.by_ref() {
+            if line.trim().is_empty() {
+                break;
+            }
+            // Continuing the header above, for long lists of recipients
+            if line.starts_with(char::is_whitespace) {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                    continue;
+                }
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                self.edited = Some(text.to_owned());
+                return Err(format!(
+                    "\"{}\" isn't a header, leave a blank line before the body",
+                    line
+                ));
+            };
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+        let body = lines.collect::<Vec<_>>().join("\n");
+        // Checked up front, so that a mistake leaves the fields as they were
+        let known = |name: &str| {
+            HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name))
+                || (name.eq_ignore_ascii_case("attachment") && self.attachment.is_some())
+        };
+        if let Some((name, _)) = headers.iter().find(|(name, _)| !known(name)) {
+            let e = format!(
+                "Unknown header \"{}\", only {} can be set",
+                name,
+                HEADERS.join(", ")
+            );
+            self.edited = Some(text);
+            return Err(e);
+        }
+        let mut attachment = None;
+        for (name, value) in headers {
+            let field = match name.to_lowercase().as_str() {
+                "from" => &mut self.from,
+                "to" => &mut self.to,
+                "cc" => &mut self.cc,
+                "bcc" => &mut self.bcc,
+                "subject" => &mut self.subject,
+                _ => {
+                    attachment = Some(value);
+                    continue;
+                }
+            };
+            *field = value;
+        }
+        match (attachment, &mut self.attachment) {
+            (Some(name), Some((file_name, _))) if !name.is_empty() => *file_name = name,
+            _ => self.attachment = None,
+        }
+        self.body = body;
+        self.edited = Some(text);
+        Ok(())
+    }
+
+    /// The message to send, as text/plain with any forwarded message attached.
+    pub fn to_message(&self) -> Result<Message, String> {
+        let from: Mailbox = self
+            .from
+            .parse()
+            .map_err(|e| format!("Invalid From \"{}\": {}", self.from, e))?;
+        let mut builder = Message::builder()
+            .from(from)
+            .subject(&self.subject)
+            .message_id(None);
+        for (name, value) in [("To", &self.to), ("Cc", &self.cc), ("Bcc", &self.bcc)] {
+            if value.is_empty() {
+                continue;
+            }
+            let mailboxes: Mailboxes = value
+                .parse()
+                .map_err(|e| format!("Invalid {} \"{}\": {}", name, value, e))?;
+            builder = match name {
+                "To" => builder.mailbox(header::To::from(mailboxes)),
+                "Cc" => builder.mailbox(header::Cc::from(mailboxes)),
+                _ => builder.mailbox(header::Bcc::from(mailboxes)),
+            };
+        }
+        if let Some(id) = &self.in_reply_to {
+            builder = builder.in_reply_to(format!("<{}>", id));
+        }
+        if !self.references.is_empty() {
+            let ids: Vec<String> = self
+                .references
+                .iter()
+                .map(|id| format!("<{}>", id))
+                .collect();
+            builder = builder.references(ids.join(" "));
+        }
+        let message = match &self.attachment {
+            None => builder
+                .header(ContentType::TEXT_PLAIN)
+                .body(self.body.to_owned()),
+            Some((name, raw)) => {
+                // Only 7bit and 8bit are allowed for message/rfc822, base64 is a last resort
+                // which most clients cope with all the same
+                let encoding = match raw.is_ascii() {
+                    true => ContentTransferEncoding::SevenBit,
+                    false => ContentTransferEncoding::EightBit,
+                };
+                let body = match String::from_utf8(raw.to_owned()) {
+                    Ok(text) => Body::new_with_encoding(text, encoding).unwrap_or_else(Body::new),
+                    Err(e) => Body::new(e.into_bytes()),
+                };
+                let content_type =
+                    ContentType::parse("message/rfc822").map_err(|e| e.to_string())?;
+                builder.multipart(
+                    MultiPart::mixed()
+                        .singlepart(SinglePart::plain(self.body.to_owned()))
+                        .singlepart(Attachment::new(name.to_owned()).body(body, content_type)),
+                )
+            }
+        };
+        message.map_err(|e| match e {
+            lettre::error::Error::MissingTo => "No recipients".to_owned(),
+            e => e.to_string(),
+        })
+    }
+}
+
+/// Open `text` in `$VISUAL` or `$EDITOR`, or else vi, and return it as saved. The terminal has to
+/// be given up to the editor meanwhile.
+pub fn edit(text: &str) -> io::Result<String> {
+    let path = temp_file(text)?;
+    let editor = env::var("VISUAL")
+        .or(env::var("EDITOR"))
+        .unwrap_or("vi".to_owned());
+    // Through the shell, since the editor may come with arguments like `code --wait`
+    let status = match cfg!(windows) {
+        true => process::Command::new("cmd")
+            .arg("/C")
+            .arg(format!("{} \"{}\"", editor, path.display()))
+            .status(),
+        // The path as an argument of the script rather than part of it, so it needs no quoting
+        false => process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(&path)
+            .status(),
+    };
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let status = status?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with {}",
+            editor, status
+        )));
+    }
+    edited
+}
+
+/// A new file in the temp directory holding `text`, readable by the current user only. The name
+/// is random and the file must not exist yet, so that nobody else sharing the directory can have
+/// put a file or a link there first.
+fn temp_file(text: &str) -> io::Result<PathBuf> {
+    let mut random = [0u8; 8];
+    getrandom::getrandom(&mut random).map_err(|e| io::Error::other(e.to_string()))?;
+    let random: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    let path = env::temp_dir().join(format!("tinbox-{}-{}.eml", process::id(), random));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&path)?.write_all(text.as_bytes())?;
+    Ok(path)
+}
+
+/// `subject` with `prefix`, unless it starts with that or one of `already`, lowercase.
+fn prefixed(prefix: &str, already: &[&str], subject: &str) -> String {
+    let lower = subject.to_lowercase();
+    match already
+        .iter()
+        .any(|p| lower.starts_with(&format!("{}:", p)))
+    {
+        true => subject.to_owned(),
+        false => format!("{}: {}", prefix, subject),
+    }
+}
+
+/// `addr` as written in a header, with the name quoted if lettre would otherwise take it apart.
+fn address(addr: &Addr) -> String {
+    let email = addr.address().unwrap_or_default();
+    match addr.name() {
+        Some(name) if !name.is_empty() => {
+            match name.chars().all(|c| c.is_alphanumeric() || c == ' ') {
+                true => format!("{} <{}>", name, email),
+                false => format!(
+                    "\"{}\" <{}>",
+                    name.replace('\\', "\\\\").replace('"', "\\\""),
+                    email
+                ),
+            }
+        }
+        _ => email.to_owned(),
+    }
+}
+
+fn addresses(addrs: &mail_parser::Address) -> String {
+    addrs.iter().map(address).collect::<Vec<_>>().join(", ")
+}
+
+/// Message-IDs of a header like References.
+fn ids(value: &HeaderValue) -> Vec<String> {
+    match value {
+        HeaderValue::Text(id) => vec![id.to_string()],
+        HeaderValue::TextList(ids) => ids.iter().map(|id| id.to_string()).collect(),
+        _ => vec![],
+    }
+}
+
+/// The text of `msg`, from its text/plain part or else its HTML as text.
+fn plain_text(msg: &mail_parser::Message) -> String {
+    match msg.text_part(0) {
+        Some(part) if part.is_text_html() => html2text::from_read(part.contents(), QUOTE_WIDTH),
+        Some(part) => part.text_contents().unwrap_or_default().to_owned(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account() -> Account {
+        Account {
+            username: "me@example.com".to_owned(),
+            from: Some("Me <Me@Example.com>".to_owned()),
+            ..Account::default()
+        }
+    }
+
+    fn respond(kind: ReplyKind, raw: &str) -> Draft {
+        Draft::respond(kind, raw.as_bytes(), 0, &account()).unwrap()
+    }
+
+    const MESSAGE: &str = "From: Alice <alice@example.org>\r\n\
+        To: me@example.com, Bob <bob@example.org>\r\n\
+        Cc: ME@example.com, carol@example.org, bob@example.org\r\n\
+        Subject: Lunch\r\n\
+        Message-ID: <2@example.org>\r\n\
+        References: <1@example.org>\r\n\
+        \r\n\
+        Noon?\r\n";
+
+    #[test]
+    fn reply_goes_to_the_author() {
+        let draft = respond(ReplyKind::Reply, MESSAGE);
+        assert_eq!(draft.to, "Alice <alice@example.org>");
+        assert_eq!(draft.cc, "");
+        assert_eq!(draft.subject, "Re: Lunch");
+        assert_eq!(draft.in_reply_to.as_deref(), Some("2@example.org"));
+        assert_eq!(draft.references, ["1@example.org", "2@example.org"]);
+        assert!(
+            draft.body.ends_with("wrote:\n> Noon?\n\n"),
+            "{}",
+            draft.body
+        );
+    }
+
+    #[test]
+    fn reply_all_drops_our_addresses_and_duplicates() {
+        let draft = respond(ReplyKind::ReplyAll, MESSAGE);
+        assert_eq!(draft.to, "Alice <alice@example.org>, Bob <bob@example.org>");
+        assert_eq!(draft.cc, "carol@example.org");
+    }
+
+    #[test]
+    fn reply_prefers_reply_to() {
+        let raw = MESSAGE.replace("Subject:", "Reply-To: list@example.org\r\nSubject:");
+        let draft = respond(ReplyKind::Reply, &raw);
+        assert_eq!(draft.to, "list@example.org");
+    }
+
+    #[test]
+    fn reply_to_our_own_message_goes_to_its_recipients() {
+        let raw = "From: me@example.com\r\nTo: dave@example.org\r\nSubject: Hi\r\n\r\nHi\r\n";
+        assert_eq!(respond(ReplyKind::Reply, raw).to, "dave@example.org");
+        // Unless it went to ourselves only
+        let raw = raw.replace("dave@example.org", "me@example.com");
+        assert_eq!(respond(ReplyKind::Reply, &raw).to, "me@example.com");
+    }
+
+    #[test]
+    fn prefixes_dont_stack() {
+        let raw =
+            |subject: &str| MESSAGE.replace("Subject: Lunch", &format!("Subject: {}", subject));
+        assert_eq!(
+            respond(ReplyKind::Reply, &raw("RE: Lunch")).subject,
+            "RE: Lunch"
+        );
+        assert_eq!(
+            respond(ReplyKind::Reply, &raw("Fwd: Lunch")).subject,
+            "Re: Fwd: Lunch"
+        );
+        assert_eq!(
+            respond(ReplyKind::Forward, &raw("Fwd: Lunch")).subject,
+            "Fwd: Lunch"
+        );
+        assert_eq!(
+            respond(ReplyKind::Forward, &raw("FW: Lunch")).subject,
+            "FW: Lunch"
+        );
+        assert_eq!(
+            respond(ReplyKind::Forward, &raw("Re: Lunch")).subject,
+            "Fwd: Re: Lunch"
+        );
+    }
+
+    #[test]
+    fn forward_quotes_the_headers() {
+        let draft = respond(ReplyKind::Forward, MESSAGE);
+        assert_eq!(draft.to, "");
+        assert!(draft.body.contains("From: Alice <alice@example.org>\n"));
+        assert!(draft.body.contains("Subject: Lunch\n"));
+        assert!(draft.body.trim_end().ends_with("Noon?"));
+    }
+
+    #[test]
+    fn forward_attached_names_the_file_after_the_subject() {
+        let raw = MESSAGE.replace("Subject: Lunch", "Subject: Lunch/dinner?");
+        let draft = respond(ReplyKind::ForwardAttached, &raw);
+        let (name, attached) = draft.attachment.unwrap();
+        assert_eq!(name, "Lunchdinner.eml");
+        assert_eq!(attached, raw.as_bytes());
+    }
+
+    #[test]
+    fn update_reads_headers_and_body() {
+        let mut draft = Draft::new(0, &account());
+        let text = "From: Me <me@example.com>\nTo: a@example.org,\n  b@example.org\n\
+            Subject: Hello\n\nBody: not a header\n";
+        draft.update(text.to_owned()).unwrap();
+        assert_eq!(draft.to, "a@example.org, b@example.org");
+        assert_eq!(draft.subject, "Hello");
+        assert_eq!(draft.body, "Body: not a header");
+        assert!(draft.was_edited());
+        assert_eq!(draft.text(), text);
+    }
+
+    #[test]
+    fn update_rejects_unknown_headers_keeping_the_text() {
+        let mut draft = Draft::new(0, &account());
+        let text = "To: a@example.org\nX-Mailer: me\n\nHi\n".to_owned();
+        assert!(draft.update(text.to_owned()).is_err());
+        assert!(draft.update("no colon here\n\nHi".to_owned()).is_err());
+        assert_eq!(draft.to, "");
+        assert_eq!(draft.text(), "no colon here\n\nHi");
+    }
+
+    #[test]
+    fn removing_the_attachment_line_drops_it() {
+        let mut draft = respond(ReplyKind::ForwardAttached, MESSAGE);
+        let text = draft
+            .text()
+            .replace("Attachment: Lunch.eml", "Attachment: lunch.eml");
+        draft.update(text).unwrap();
+        assert_eq!(draft.attachment.as_ref().unwrap().0, "lunch.eml");
+        let text = draft.text().replace("Attachment: lunch.eml\n", "");
+        draft.update(text).unwrap();
+        assert!(draft.attachment.is_none());
+    }
+
+    #[test]
+    fn message_threads_the_reply() {
+        let draft = respond(ReplyKind::Reply, MESSAGE);
+        let message = String::from_utf8(draft.to_message().unwrap().formatted()).unwrap();
+        assert!(
+            message.contains("In-Reply-To: <2@example.org>\r\n"),
+            "{}",
+            message
+        );
+        assert!(message.contains("References: <1@example.org> <2@example.org>\r\n"));
+        assert!(Draft::new(0, &account()).to_message().is_err());
+    }
+}
@@ -1,18 +1,170 @@
+use std::{
+    collections::BTreeMap,
+    process::{Command, Stdio},
+};
+
+use chrono::format::{Item, StrftimeItems};
 use confy::ConfyError;
 use serde::{Deserialize, Serialize};
 
-use crate::app::Context;
+use crate::{app::Context, credentials};
 
 pub static APP_NAME: &str = "tinbox";
 pub static CONFIG_FILE: &str = "login";
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct Config {
+    /// Every account to show. When empty, `account` is used alone.
+    pub accounts: Vec<Account>,
+    /// The one account of a config written before there could be several, with its fields at
+    /// the top level
+    #[serde(flatten)]
+    pub account: Account,
+    pub date_format: DateFormat,
+    /// Number of messages loaded at a time, more are fetched when scrolling near the end
+    pub page_size: u32,
+    /// Key bindings by scope, like `[keys.emails]` with `"dd" = "delete"`, see [`Keymap::new`]
+    ///
+    /// [`Keymap::new`]: crate::keymap::Keymap::new
+    pub keys: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            accounts: vec![],
+            account: Account::default(),
+            date_format: DateFormat::default(),
+            page_size: 100,
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+/// An IMAP account, each with a connection of its own.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Account {
+    /// Shown in the sidebar, the username if empty
+    pub name: String,
     pub username: String,
+    /// Better left empty in favour of `password_command` or the credential store, filled in at
+    /// startup from either
     pub password: String,
+    /// Shell command printing the password on its first line, like `pass show mail`
+    pub password_command: Option<String>,
+    /// Log in with OAuth2 instead of a password
+    pub oauth: Option<OAuth>,
+    pub security: Security,
+    /// PEM file of certificates to trust besides the system's, for servers with a private CA
+    pub ca_file: Option<String>,
+    /// SHA-256 fingerprint of the server's certificate, trusted in place of any CA. For
+    /// self-signed servers, see `openssl x509 -noout -fingerprint -sha256`
+    pub cert_fingerprint: Option<String>,
+    /// PEM certificate to authenticate with, for servers that ask for one
+    pub client_cert: Option<String>,
+    /// PKCS #8 PEM key of `client_cert`
+    pub client_key: Option<String>,
     pub imap_server: String,
     pub imap_port: u16,
+    /// Destination of the archive action, overriding the server's \Archive mailbox
+    pub archive_folder: Option<String>,
+    /// Destination of the trash action, overriding the server's \Trash mailbox
+    pub trash_folder: Option<String>,
+    /// Address to send from, like `Jane Doe <jane@example.com>`, the username if unset
+    pub from: Option<String>,
+    /// Server to send mail through, sending being unavailable without one
+    pub smtp: Option<Smtp>,
+    /// Mailbox to keep a copy of sent messages in. Best left unset for servers that keep one
+    /// themselves, like Gmail
+    pub sent_folder: Option<String>,
+}
+
+/// The SMTP server of an account, logged in to with the account's credentials. The `ca_file`,
+/// `client_cert` and `client_key` of the account apply to it as well.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Smtp {
+    pub server: String,
+    /// 465 for `tls`, 587 for `starttls` and 25 for `none` if unset
+    pub port: u16,
+    /// `none` with an empty password suits a local stand-in server
+    pub security: Security,
+    /// The account's username if unset
+    pub username: Option<String>,
+}
+
+/// How the connection to the server is secured.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// TLS from the start, usually on port 993
+    #[default]
+    Tls,
+    /// Plain connection upgraded with STARTTLS, usually on port 143
+    StartTls,
+    /// Unencrypted, only sensible for servers on the same machine
+    None,
+}
+
+/// OAuth2 login, for providers like Gmail and Microsoft 365 that refuse passwords. The access
+/// token comes from `token_command` if set, otherwise it is refreshed at `token_url` with the
+/// account's password, wherever that comes from, as the refresh token.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct OAuth {
+    pub mechanism: Mechanism,
+    /// Shell command printing a valid access token, like `oama access me@gmail.com`
+    pub token_command: Option<String>,
+    /// Token endpoint of the provider, like `https://oauth2.googleapis.com/token`
+    pub token_url: Option<String>,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+/// SASL mechanism to present the access token with.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mechanism {
+    #[default]
+    XOAuth2,
+    OAuthBearer,
+}
+
+/// strftime-style formats for the date column, picked by how long ago a message arrived.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DateFormat {
+    /// Messages from today
+    pub today: String,
+    /// Messages from the last seven days
+    pub week: String,
+    /// Anything older
+    pub older: String,
+    /// When false, `older` is used for every message
+    pub relative: bool,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        DateFormat {
+            today: "%-I:%M %p".to_owned(),
+            week: "%a %-I:%M %p".to_owned(),
+            older: "%b %-d %Y".to_owned(),
+            relative: true,
+        }
+    }
+}
+
+impl DateFormat {
+    /// Returns the first format string chrono cannot parse, since formatting with it would panic.
+    pub fn invalid(&self) -> Option<&str> {
+        [&self.today, &self.week, &self.older]
+            .into_iter()
+            .find(|f| StrftimeItems::new(f).any(|i| i == Item::Error))
+            .map(|f| f.as_str())
+    }
 }
 
 impl Config {
@@ -24,5 +176,166 @@ impl Config {
     // }
     pub fn apply(&self, ctx: &mut Context) {
         ctx.config = self.to_owned();
+        ctx.accounts = self.accounts();
+    }
+
+    /// The configured accounts, with names filled in.
+    pub fn accounts(&self) -> Vec<Account> {
+        let accounts = match self.accounts.is_empty() {
+            true => vec![self.account.to_owned()],
+            false => self.accounts.to_owned(),
+        };
+        accounts
+            .into_iter()
+            .map(|a| match a.name.is_empty() {
+                true => Account {
+                    name: a.username.to_owned(),
+                    ..a
+                },
+                false => a,
+            })
+            .collect()
+    }
+
+    /// Fill in the password of every account that has none in the config, from its
+    /// `password_command` or else the credential store, whose passphrase is asked for once. Both
+    /// may prompt on the terminal, so this runs before the app takes it over. Accounts with no
+    /// password anywhere are left to fail logging in.
+    pub fn resolve_passwords(&mut self) -> Result<(), String> {
+        let mut accounts = self.accounts();
+        let mut store = None;
+        let needs_password = |a: &Account| match &a.oauth {
+            Some(oauth) => oauth.token_command.is_none(),
+            None => true,
+        };
+        let missing = accounts
+            .iter_mut()
+            .filter(|a| a.password.is_empty() && needs_password(a));
+        for a in missing {
+            if let Some(cmd) = &a.password_command {
+                a.password = run_secret_command(cmd, true)
+                    .map_err(|e| format!("password_command of {} failed: {}", a.name, e))?;
+                continue;
+            }
+            if !credentials::Credentials::exists() {
+                continue;
+            }
+            if store.is_none() {
+                store = Some(credentials::prompt_unlock()?);
+            }
+            if let Some(password) = store.as_ref().and_then(|s| s.get(&a.name)) {
+                a.password = password.to_owned();
+            }
+        }
+        self.accounts = accounts;
+        Ok(())
+    }
+}
+
+/// Run `cmd` in the shell and return the first line it prints. Only an `interactive` command,
+/// run before the UI takes over the terminal, gets the terminal's stdin and stderr, letting gpg
+/// and friends ask for their own passphrase. Otherwise what it prints to stderr ends up in the
+/// error.
+pub fn run_secret_command(cmd: &str, interactive: bool) -> Result<String, String> {
+    let mut shell = match cfg!(windows) {
+        true => Command::new("cmd"),
+        false => Command::new("sh"),
+    };
+    shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(cmd);
+    let output = match interactive {
+        true => shell.stdin(Stdio::inherit()).stderr(Stdio::inherit()),
+        false => shell.stdin(Stdio::null()).stderr(Stdio::piped()),
+    }
+    .output()
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => output.status.to_string(),
+            stderr => format!("{}: {}", output.status, stderr),
+        });
+    }
+    let stdout = String::from_utf8(output.stdout).map_err(|e| e.to_string())?;
+    match stdout.lines().next() {
+        Some(line) if !line.is_empty() => Ok(line.to_owned()),
+        _ => Err("nothing printed".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_single_account_at_the_top_level() {
+        let config: Config = toml::from_str(
+            r#"
+            username = "me@example.com"
+            imap_server = "imap.example.com"
+            imap_port = 993
+            security = "starttls"
+            page_size = 50
+
+            [smtp]
+            server = "smtp.example.com"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.page_size, 50);
+        let accounts = config.accounts();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].name, "me@example.com");
+        assert_eq!(accounts[0].imap_port, 993);
+        assert!(matches!(accounts[0].security, Security::StartTls));
+        assert_eq!(
+            accounts[0].smtp.as_ref().unwrap().server,
+            "smtp.example.com"
+        );
+    }
+
+    #[test]
+    fn accounts_replace_the_top_level_one() {
+        let config: Config = toml::from_str(
+            r#"
+            username = "ignored"
+
+            [[accounts]]
+            name = "work"
+            username = "me@work.example"
+
+            [[accounts]]
+            username = "me@home.example"
+            "#,
+        )
+        .unwrap();
+        let names: Vec<String> = config.accounts().into_iter().map(|a| a.name).collect();
+        assert_eq!(names, ["work", "me@home.example"]);
+    }
+
+    #[test]
+    fn default_config_survives_a_round_trip() {
+        let written = toml::to_string(&Config::default()).unwrap();
+        let config: Config = toml::from_str(&written).unwrap();
+        assert_eq!(config.page_size, 100);
+        assert!(config.accounts.is_empty());
+    }
+
+    #[test]
+    fn default_date_formats_are_valid() {
+        assert_eq!(DateFormat::default().invalid(), None);
+    }
+
+    #[test]
+    fn rejects_unknown_date_specifiers() {
+        let formats = DateFormat {
+            week: "%Q".to_owned(),
+            ..DateFormat::default()
+        };
+        assert_eq!(formats.invalid(), Some("%Q"));
+        let formats = DateFormat {
+            older: "%Y-%".to_owned(),
+            ..DateFormat::default()
+        };
+        assert_eq!(formats.invalid(), Some("%Y-%"));
     }
 }
@@ -0,0 +1,369 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    mem,
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use imap::{extensions::idle::SetReadTimeout, Client};
+use native_tls::{Certificate, Identity, TlsConnector, TlsStream};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{Account, Security},
+    error::{Error, Result},
+};
+
+/// How long to wait for the server to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long a read may block before the connection is given up on. Without it a connection that
+/// died while the machine slept would hang until TCP itself gives up, many minutes later.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest wait between attempts to get a lost connection back.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Connection to an IMAP server, encrypted unless the account opted out.
+enum Transport {
+    Tls(TlsStream<TcpStream>),
+    Plain(TcpStream),
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tls(s) => s.read(buf),
+            Transport::Plain(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Tls(s) => s.write(buf),
+            Transport::Plain(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Tls(s) => s.flush(),
+            Transport::Plain(s) => s.flush(),
+        }
+    }
+}
+
+thread_local! {
+    /// Untagged responses read on this thread that the imap crate has no parser for.
+    static UNPARSED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Take the ENABLED (RFC 5161) and VANISHED (RFC 7162) responses read on this thread since the
+/// last call. The imap crate can't parse those, and gives up on the whole response when it meets
+/// one, so [`Stream`] keeps them from it.
+pub fn take_unparsed() -> Vec<String> {
+    UNPARSED.with(|u| u.take())
+}
+
+/// The connection as the imap crate reads it, a line at a time, less the responses left for
+/// [`take_unparsed`].
+pub struct Stream {
+    reader: BufReader<Transport>,
+    /// The line being read, kept when a read times out halfway through it
+    line: Vec<u8>,
+    /// The last line read, and how much of it was handed out
+    out: Vec<u8>,
+    pos: usize,
+    /// Bytes left of a literal, passed on without looking for responses in them
+    literal: usize,
+}
+
+impl Stream {
+    fn new(transport: Transport) -> Self {
+        Stream {
+            reader: BufReader::new(transport),
+            line: Vec::new(),
+            out: Vec::new(),
+            pos: 0,
+            literal: 0,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.out.len() {
+                let n = (&self.out[self.pos..]).read(buf)?;
+                self.pos += n;
+                return Ok(n);
+            }
+            if self.literal > 0 {
+                let len = buf.len().min(self.literal);
+                let n = self.reader.read(&mut buf[..len])?;
+                self.literal -= n;
+                return Ok(n);
+            }
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 && self.line.is_empty() {
+                return Ok(0);
+            }
+            self.out = mem::take(&mut self.line);
+            self.pos = 0;
+            if unparsed(&self.out) {
+                let line = String::from_utf8_lossy(&self.out).trim_end().to_owned();
+                UNPARSED.with(|u| u.borrow_mut().push(line));
+                self.out.clear();
+            } else {
+                self.literal = literal_len(&self.out);
+            }
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.get_mut().flush()
+    }
+}
+
+impl SetReadTimeout for Stream {
+    /// IDLE sets its own timeout while waiting and clears it after, which restores ours.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::Result<()> {
+        let timeout = timeout.or(Some(READ_TIMEOUT));
+        match self.reader.get_ref() {
+            Transport::Tls(s) => s.get_ref().set_read_timeout(timeout),
+            Transport::Plain(s) => TcpStream::set_read_timeout(s, timeout),
+        }
+        .map_err(imap::Error::Io)
+    }
+}
+
+/// Whether `line` is a whole response the imap crate can't parse.
+fn unparsed(line: &[u8]) -> bool {
+    let starts = |prefix: &[u8]| {
+        line.get(..prefix.len())
+            .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+    };
+    line.ends_with(b"\n") && (starts(b"* VANISHED ") || starts(b"* ENABLED"))
+}
+
+/// Length of the literal `line` announces at its end, if any.
+fn literal_len(line: &[u8]) -> usize {
+    let Some(rest) = line.strip_suffix(b"}\r\n") else {
+        return 0;
+    };
+    let Some(start) = rest.iter().rposition(|&b| b == b'{') else {
+        return 0;
+    };
+    std::str::from_utf8(&rest[start + 1..])
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Connect to the server of `account` as its `security` says, ready to log in.
+pub fn connect(account: &Account) -> Result<Client<Stream>> {
+    let domain = account.imap_server.as_str();
+    let tcp = tcp_connect(domain, account.imap_port)
+        .map_err(|e| Error::Network(format!("connecting to {} failed: {}", domain, e)))?;
+    tcp.set_read_timeout(Some(READ_TIMEOUT))?;
+    tcp.set_write_timeout(Some(READ_TIMEOUT))?;
+    let mut client = match account.security {
+        Security::Tls => Client::new(Stream::new(Transport::Tls(handshake(account, tcp)?))),
+        Security::StartTls => {
+            starttls(&tcp)?;
+            // The greeting was read before upgrading, none follows the handshake
+            return Ok(Client::new(Stream::new(Transport::Tls(handshake(
+                account, tcp,
+            )?))));
+        }
+        Security::None => Client::new(Stream::new(Transport::Plain(tcp))),
+    };
+    client.read_greeting()?;
+    Ok(client)
+}
+
+/// The next wait between attempts to reconnect, doubling the `previous` one.
+pub fn backoff(previous: Option<Duration>) -> Duration {
+    match previous {
+        Some(d) => (d * 2).min(MAX_BACKOFF),
+        None => Duration::from_secs(1),
+    }
+}
+
+/// Try each address `domain` resolves to in turn, as [`TcpStream::connect`] does, only with a
+/// timeout.
+fn tcp_connect(domain: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, "no address found");
+    for addr in (domain, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// Read the greeting and ask to upgrade the connection, before the imap client takes it over.
+fn starttls(tcp: &TcpStream) -> Result<()> {
+    // Nothing is sent after the tagged OK until the handshake, so buffering can't lose any of it
+    let mut reader = BufReader::new(tcp);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("* OK") {
+        return Err(Error::Protocol(format!(
+            "unexpected greeting: {}",
+            line.trim_end()
+        )));
+    }
+    let mut writer = tcp;
+    writer.write_all(b"a0 STARTTLS\r\n")?;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::Network(
+                "connection closed during STARTTLS".to_owned(),
+            ));
+        }
+        if let Some(status) = line.strip_prefix("a0 ") {
+            return match status.starts_with("OK") {
+                true => Ok(()),
+                false => Err(Error::Protocol(format!(
+                    "STARTTLS refused: {}",
+                    status.trim_end()
+                ))),
+            };
+        }
+    }
+}
+
+fn handshake(account: &Account, tcp: TcpStream) -> Result<TlsStream<TcpStream>> {
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = &account.ca_file {
+        let pem = fs::read_to_string(path).map_err(|e| read_error(path, e))?;
+        for cert in pem_certificates(&pem) {
+            builder.add_root_certificate(Certificate::from_pem(cert.as_bytes())?);
+        }
+    }
+    if let (Some(cert), Some(key)) = (&account.client_cert, &account.client_key) {
+        let cert = fs::read(cert).map_err(|e| read_error(cert, e))?;
+        let key = fs::read(key).map_err(|e| read_error(key, e))?;
+        builder.identity(Identity::from_pkcs8(&cert, &key)?);
+    }
+    // A pinned certificate stands in for the usual chain and hostname checks
+    if account.cert_fingerprint.is_some() {
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    let stream = builder
+        .build()?
+        .connect(&account.imap_server, tcp)
+        .map_err(|e| {
+            Error::Network(format!(
+                "TLS handshake with {} failed: {}",
+                account.imap_server, e
+            ))
+        })?;
+    if let Some(pin) = &account.cert_fingerprint {
+        let cert = stream
+            .peer_certificate()?
+            .ok_or(Error::Network("the server sent no certificate".to_owned()))?;
+        check_fingerprint(&account.imap_server, pin, &cert.to_der()?)?;
+    }
+    Ok(stream)
+}
+
+/// Make sure the DER certificate `server` sent is the one `pin`ned by its fingerprint.
+pub fn check_fingerprint(server: &str, pin: &str, der: &[u8]) -> Result<()> {
+    let fingerprint = fingerprint(der);
+    if normalize(pin) != fingerprint {
+        return Err(Error::Network(format!(
+            "certificate of {} doesn't match cert_fingerprint, it is {}",
+            server, fingerprint
+        )));
+    }
+    Ok(())
+}
+
+pub fn read_error(path: &str, e: io::Error) -> Error {
+    Error::Network(format!("reading {} failed: {}", path, e))
+}
+
+/// Each certificate of a PEM bundle, which native-tls only reads one of at a time.
+pub fn pem_certificates(pem: &str) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    pem.split_inclusive(END)
+        .filter(|c| c.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|c| c.trim().to_owned())
+        .collect()
+}
+
+/// SHA-256 of a DER certificate, as lowercase hex.
+fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Accept fingerprints as printed by openssl, with colons and in uppercase.
+fn normalize(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn sets_aside_responses_the_imap_crate_cant_parse() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(
+                    b"* ENABLED QRESYNC\r\n\
+                      * VANISHED (EARLIER) 3:5\r\n\
+                      * 1 FETCH (UID 7 BODY[] {22}\r\n\
+                      * VANISHED 9\r\nhi there)\r\n\
+                      a1 OK done\r\n",
+                )
+                .unwrap();
+        });
+        let mut stream = Stream::new(Transport::Plain(TcpStream::connect(addr).unwrap()));
+        let mut read = String::new();
+        stream.read_to_string(&mut read).unwrap();
+        server.join().unwrap();
+        // The one inside the literal is message text
+        assert_eq!(
+            read,
+            "* 1 FETCH (UID 7 BODY[] {22}\r\n* VANISHED 9\r\nhi there)\r\na1 OK done\r\n"
+        );
+        assert_eq!(
+            take_unparsed(),
+            ["* ENABLED QRESYNC", "* VANISHED (EARLIER) 3:5"]
+        );
+        assert!(take_unparsed().is_empty());
+    }
+
+    #[test]
+    fn literal_lengths() {
+        assert_eq!(literal_len(b"* 1 FETCH (BODY[] {22}\r\n"), 22);
+        assert_eq!(literal_len(b"* 1 FETCH (FLAGS (\\Seen))\r\n"), 0);
+        assert_eq!(literal_len(b"* OK {not a literal}\r\n"), 0);
+    }
+}
@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Mutex};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::config::APP_NAME;
+
+/// Marks the file format, in case it ever has to change.
+const MAGIC: &[u8] = b"tinbox-credentials-v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Passwords of accounts kept encrypted in `<config dir>/credentials`, unlocked with a passphrase
+/// asked for at startup. The key is derived from the passphrase with Argon2id and the passwords
+/// are sealed with XChaCha20-Poly1305, so a wrong passphrase and a tampered file look the same.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Credentials {
+    /// Keyed by account name
+    passwords: BTreeMap<String, String>,
+}
+
+/// The store as unlocked at startup, with a key to seal it again, so that refresh tokens the
+/// OAuth provider rotates can be written back without asking for the passphrase again.
+struct Unlocked {
+    credentials: Credentials,
+    cipher: XChaCha20Poly1305,
+    salt: [u8; SALT_LEN],
+}
+
+static UNLOCKED: Mutex<Option<Unlocked>> = Mutex::new(None);
+
+impl Credentials {
+    pub fn path() -> Option<PathBuf> {
+        let dirs = ProjectDirs::from("rs", "", APP_NAME)?;
+        Some(dirs.config_dir().join("credentials"))
+    }
+
+    pub fn exists() -> bool {
+        Self::path().is_some_and(|p| p.exists())
+    }
+
+    /// Decrypt the stored passwords, or start with none if nothing was stored yet.
+    pub fn unlock(passphrase: &str) -> Result<Credentials, String> {
+        let path = Self::path().ok_or("No config directory to keep credentials in")?;
+        let data = match fs::read(&path) {
+            Ok(d) => d,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Credentials::default()),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let sealed = data
+            .strip_prefix(MAGIC)
+            .filter(|d| d.len() >= SALT_LEN + NONCE_LEN)
+            .ok_or(format!("{} is not a credential file", path.display()))?;
+        let (salt, rest) = sealed.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let plain = cipher(passphrase, salt)?
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Wrong passphrase")?;
+        let plain = String::from_utf8(plain).map_err(|e| e.to_string())?;
+        toml::from_str(&plain).map_err(|e| e.to_string())
+    }
+
+    /// Encrypt the passwords with `passphrase`, replacing the stored ones.
+    pub fn save(&self, passphrase: &str) -> Result<(), String> {
+        let salt = new_salt()?;
+        self.seal(&cipher(passphrase, &salt)?, &salt)
+    }
+
+    /// Encrypt the passwords with the key derived with `salt`, replacing the stored ones.
+    fn seal(&self, cipher: &XChaCha20Poly1305, salt: &[u8]) -> Result<(), String> {
+        let path = Self::path().ok_or("No config directory to keep credentials in")?;
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|e| e.to_string())?;
+        let plain = toml::to_string(self).map_err(|e| e.to_string())?;
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), plain.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let data = [MAGIC, salt, &nonce, &ciphertext].concat();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        write_private(&path, &data)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn get(&self, account: &str) -> Option<&str> {
+        self.passwords.get(account).map(|p| p.as_str())
+    }
+
+    pub fn set(&mut self, account: &str, password: String) {
+        self.passwords.insert(account.to_owned(), password);
+    }
+}
+
+fn new_salt() -> Result<[u8; SALT_LEN], String> {
+    let mut salt = [0; SALT_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| e.to_string())?;
+    Ok(salt)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, String> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Write `data` readable by the current user only, where the platform has such a thing.
+#[cfg(unix)]
+fn write_private(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(data)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    fs::write(path, data)
+}
+
+/// Ask for the passphrase of the store and unlock it, before the terminal is taken over. It is
+/// kept unlocked for [`update`].
+pub fn prompt_unlock() -> Result<Credentials, String> {
+    let passphrase = rpassword::prompt_password("Passphrase for stored credentials: ")
+        .map_err(|e| e.to_string())?;
+    let credentials = Credentials::unlock(&passphrase)?;
+    let salt = new_salt()?;
+    let unlocked = Unlocked {
+        credentials: credentials.clone(),
+        cipher: cipher(&passphrase, &salt)?,
+        salt,
+    };
+    *UNLOCKED.lock().map_err(|e| e.to_string())? = Some(unlocked);
+    Ok(credentials)
+}
+
+/// Replace the stored password of `account` with `password`, if the store was unlocked and
+/// holds one for it. A password from anywhere else is taken over the store's at startup, so
+/// writing it there would change nothing.
+pub fn update(account: &str, password: &str) -> Result<(), String> {
+    let mut unlocked = UNLOCKED.lock().map_err(|e| e.to_string())?;
+    let Some(u) = unlocked.as_mut() else {
+        return Ok(());
+    };
+    if u.credentials.get(account).is_none_or(|p| p == password) {
+        return Ok(());
+    }
+    u.credentials.set(account, password.to_owned());
+    u.credentials.seal(&u.cipher, &u.salt)
+}
+
+/// Ask for the password of `account` and keep it in the store, creating it if needed.
+pub fn store_password(account: &str) -> Result<(), String> {
+    let read = |prompt: &str| rpassword::prompt_password(prompt).map_err(|e| e.to_string());
+    let passphrase = read("Passphrase for stored credentials: ")?;
+    let mut credentials = Credentials::unlock(&passphrase)?;
+    if !Credentials::exists() && read("Repeat passphrase: ")? != passphrase {
+        return Err("Passphrases don't match".to_owned());
+    }
+    credentials.set(account, read(&format!("Password for {}: ", account))?);
+    credentials.save(&passphrase)
+}
@@ -1,117 +1,483 @@
-use std::{cmp, error::Error, net::TcpStream};
+use std::ops::RangeInclusive;
 
-use imap::Session;
-use mail_parser::DateTime;
-use native_tls::TlsStream;
+use chrono::DateTime;
+use imap::{
+    types::{Fetch, Flag, Mailbox, NameAttribute, StatusAttribute, UnsolicitedResponse},
+    Session,
+};
+use mail_parser::MimeHeaders;
+use serde::{Deserialize, Serialize};
 use unicode_width::UnicodeWidthChar;
 
-use crate::{config::Config, widget::emails::EmailEntry};
+use crate::{
+    config::Account,
+    connection::{self, Stream},
+    error::{Error, Result},
+    oauth,
+    widget::emails::{EmailEntry, Flags},
+};
 
-pub type TlsSession = Session<TlsStream<TcpStream>>;
+pub type ImapSession = Session<Stream>;
 
-pub fn new_session(conf: Config) -> Result<TlsSession, Box<dyn Error>> {
-    let domain = conf.imap_server;
-    let tls = native_tls::TlsConnector::builder().build().unwrap();
+/// The flags tinbox lets the user change.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MailFlag {
+    Seen,
+    Flagged,
+    Deleted,
+}
+
+impl MailFlag {
+    fn name(&self) -> &'static str {
+        match self {
+            MailFlag::Seen => "\\Seen",
+            MailFlag::Flagged => "\\Flagged",
+            MailFlag::Deleted => "\\Deleted",
+        }
+    }
+}
+
+impl From<&[Flag<'_>]> for Flags {
+    fn from(flags: &[Flag<'_>]) -> Self {
+        Flags {
+            seen: flags.contains(&Flag::Seen),
+            flagged: flags.contains(&Flag::Flagged),
+            answered: flags.contains(&Flag::Answered),
+            deleted: flags.contains(&Flag::Deleted),
+        }
+    }
+}
+
+/// Format `uids` as an IMAP sequence set.
+pub fn uid_set(uids: &[u32]) -> String {
+    uids.iter()
+        .map(|u| u.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
-    let client = imap::connect((domain.clone(), conf.imap_port), domain, &tls).unwrap();
+pub fn new_session(account: Account) -> Result<ImapSession> {
+    let client = connection::connect(&account)?;
 
-    let x = client
-        .login(conf.username, conf.password)
-        .map_err(|e| e.0)?;
+    let x = match &account.oauth {
+        Some(oauth) => oauth::authenticate(client, &account, oauth)?,
+        None => client
+            .login(account.username, account.password)
+            .map_err(|(e, _)| match e {
+                imap::Error::No(e) => Error::Auth(e),
+                e => e.into(),
+            })?,
+    };
     Ok(x)
 }
 
-pub fn top_messages(
-    session: &mut TlsSession,
+pub fn select(session: &mut ImapSession, inbox: String) -> Result<Mailbox> {
+    Ok(session.select(inbox)?)
+}
+
+/// How much the server can tell about what changed in a mailbox since a modification sequence
+/// (RFC 7162).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Tracking {
+    /// Nothing, changes are found by comparing UIDs and flags
+    Compare,
+    /// Which flags changed, see [`highest_modseq`] and CHANGEDSINCE in [`fetch_flags`]
+    CondStore,
+    /// Which messages were expunged as well, see [`fetch_changes`]. Only once [`enable_qresync`]
+    /// was called.
+    QResync,
+}
+
+/// What the server can tell about changes, by its capabilities.
+pub fn tracking(session: &mut ImapSession) -> Result<Tracking> {
+    let caps = session.capabilities()?;
+    Ok(match (caps.has_str("QRESYNC"), caps.has_str("CONDSTORE")) {
+        (true, _) => Tracking::QResync,
+        (false, true) => Tracking::CondStore,
+        (false, false) => Tracking::Compare,
+    })
+}
+
+/// Turn QRESYNC on if the server has it, which can only be done before selecting a mailbox.
+/// Expunges are then reported in VANISHED responses rather than EXPUNGE ones, so this isn't for a
+/// session that is waiting for those.
+pub fn enable_qresync(session: &mut ImapSession) -> Result<()> {
+    if session.capabilities()?.has_str("QRESYNC") {
+        session.run_command_and_check_ok("ENABLE QRESYNC")?;
+        // The ENABLED response, nothing to learn from as the server must allow it
+        connection::take_unparsed();
+    }
+    Ok(())
+}
+
+/// HIGHESTMODSEQ of `inbox`, asked with STATUS before selecting it. Servers may answer STATUS on
+/// the already selected mailbox with a stale value, which is harmless as long as it is taken
+/// before fetching changes: a lower value only means some flags are fetched twice.
+pub fn highest_modseq(session: &mut ImapSession, inbox: &str) -> Result<Option<u64>> {
+    session.status(inbox, "(HIGHESTMODSEQ)")?;
+    Ok(session
+        .unsolicited_responses
+        .try_iter()
+        .filter_map(|r| match r {
+            UnsolicitedResponse::Status { attributes, .. } => {
+                attributes.into_iter().find_map(|a| match a {
+                    StatusAttribute::HighestModSeq(m) => Some(m),
+                    _ => None,
+                })
+            }
+            _ => None,
+        })
+        .last())
+}
+
+/// Flags of the messages in the selected mailbox with UIDs from `lo` to `hi`, only of those
+/// changed after `changed_since` if given.
+pub fn fetch_flags(
+    session: &mut ImapSession,
+    lo: u32,
+    hi: u32,
+    changed_since: Option<u64>,
+) -> Result<Vec<(u32, Flags)>> {
+    let query = match changed_since {
+        Some(modseq) => format!("(UID FLAGS) (CHANGEDSINCE {})", modseq),
+        None => "(UID FLAGS)".to_owned(),
+    };
+    let fetches = session.uid_fetch(format!("{}:{}", lo, hi), query)?;
+    Ok(fetches
+        .iter()
+        .filter_map(|f| Some((f.uid?, Flags::from(f.flags()))))
+        .collect())
+}
+
+/// What [`fetch_changes`] found.
+pub struct Changed {
+    pub flags: Vec<(u32, Flags)>,
+    /// UIDs expunged, as ranges that may well be larger than the mailbox
+    pub vanished: Vec<RangeInclusive<u32>>,
+}
+
+/// Like [`fetch_flags`] with CHANGEDSINCE, on a session with QRESYNC enabled, also telling which
+/// UIDs from `lo` to `hi` were expunged since `changed_since`.
+pub fn fetch_changes(
+    session: &mut ImapSession,
+    lo: u32,
+    hi: u32,
+    changed_since: u64,
+) -> Result<Changed> {
+    // Left over from earlier commands, maybe in another mailbox
+    connection::take_unparsed();
+    let query = format!("(UID FLAGS) (CHANGEDSINCE {} VANISHED)", changed_since);
+    let fetches = session.uid_fetch(format!("{}:{}", lo, hi), query)?;
+    let flags = fetches
+        .iter()
+        .filter_map(|f| Some((f.uid?, Flags::from(f.flags()))))
+        .collect();
+    // Besides VANISHED (EARLIER), messages expunged meanwhile may be reported on their own
+    let vanished = connection::take_unparsed()
+        .iter()
+        .filter_map(|line| vanished(line))
+        .flatten()
+        .filter(|r| r.start() <= &hi && r.end() >= &lo)
+        .collect();
+    Ok(Changed { flags, vanished })
+}
+
+/// The UIDs a VANISHED `line` reports expunged.
+fn vanished(line: &str) -> Option<Vec<RangeInclusive<u32>>> {
+    let mut words = line.split_ascii_whitespace();
+    if words.next() != Some("*") || !words.next()?.eq_ignore_ascii_case("VANISHED") {
+        return None;
+    }
+    let set = words.find(|w| !w.eq_ignore_ascii_case("(EARLIER)"))?;
+    set.split(',')
+        .map(|range| {
+            let (a, b) = range.split_once(':').unwrap_or((range, range));
+            let (a, b): (u32, u32) = (a.parse().ok()?, b.parse().ok()?);
+            Some(a.min(b)..=a.max(b))
+        })
+        .collect()
+}
+
+/// Everything the message list needs, without downloading any message bodies.
+const ENVELOPE_QUERY: &str =
+    "(UID FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[HEADER.FIELDS (FROM SUBJECT DATE CONTENT-TYPE)])";
+
+/// Fetch the list entries for the messages in the sequence set `query`, newest first. Bodies are
+/// left on the server until a message is previewed.
+pub fn fetch_envelopes(
+    session: &mut ImapSession,
+    query: String,
+    inbox: &str,
+    uid_validity: u32,
+) -> Result<Vec<EmailEntry>> {
+    let messages = session.fetch(query, ENVELOPE_QUERY)?;
+    Ok(messages
+        .iter()
+        .rev()
+        .map(|m| to_entry(m, inbox, uid_validity))
+        .collect())
+}
+
+/// Like [`fetch_envelopes`], for a set of UIDs.
+pub fn uid_fetch_envelopes(
+    session: &mut ImapSession,
+    uids: &[u32],
+    inbox: &str,
+    uid_validity: u32,
+) -> Result<Vec<EmailEntry>> {
+    let messages = session.uid_fetch(uid_set(uids), ENVELOPE_QUERY)?;
+    Ok(messages
+        .iter()
+        .rev()
+        .map(|m| to_entry(m, inbox, uid_validity))
+        .collect())
+}
+
+/// The list entry for `message`. One whose headers are missing or can't be parsed still gets a
+/// placeholder entry, so it can be seen and acted on.
+fn to_entry(message: &Fetch, inbox: &str, uid_validity: u32) -> EmailEntry {
+    let parsed = message
+        .header()
+        .and_then(|h| mail_parser::MessageParser::new().parse_headers(h));
+    let unreadable = parsed.is_none();
+    let msg = parsed.unwrap_or_default();
+    let from = msg
+        .from()
+        .and_then(|f| f.first())
+        .and_then(|f| f.name.clone().or(f.address.clone()))
+        .map(|n| n.to_string())
+        .unwrap_or_default();
+    let subject = match unreadable {
+        true => "(couldn't read this message)".to_owned(),
+        false => msg.subject().map(|s| s.to_owned()).unwrap_or_default(),
+    };
+    let subject = subject
+        .chars()
+        .filter(|c| c.width().is_some_and(|c| c != 0)) // Remove 0 width chars
+        .collect();
+    let date = msg
+        .date()
+        .filter(|d| d.is_valid())
+        .and_then(|d| DateTime::parse_from_rfc3339(&d.to_rfc3339()).ok())
+        .or(message.internal_date());
+    // let prio = msg
+    //     .header_values("X-Priority")
+    //     .next()
+    //     .and_then(|x| x.as_text())
+    //     .unwrap_or_default()
+    //     .to_owned();
+    // let date = prio;
+    // let prio = msg
+    //     .headers()
+    //     .iter()
+    //     .rev()
+    //     .find(|h| h.name.to_string().to_lowercase() == "x-priority");
+    // let date = prio
+    //     .and_then(|p| p.value().as_text())
+    //     .map(|x| x.to_owned())
+    //     .unwrap_or_default();
+    // Without BODYSTRUCTURE this is a guess, but multipart/mixed is what mail clients
+    // use to attach files next to the body
+    let attachment = msg
+        .content_type()
+        .is_some_and(|c| c.c_type == "multipart" && c.subtype() == Some("mixed"));
+    EmailEntry {
+        // Filled in by the worker handing it out
+        account: 0,
+        inbox: inbox.to_owned(),
+        uid: message.uid.unwrap_or_default(),
+        uid_validity,
+        from,
+        subject,
+        date,
+        flags: Flags::from(message.flags()),
+        attachment,
+        unreadable,
+        marked: false,
+    }
+}
+
+/// UIDs of the messages in the selected mailbox matching the IMAP `criteria`, newest first.
+pub fn search(session: &mut ImapSession, criteria: String) -> Result<Vec<u32>> {
+    let mut uids: Vec<u32> = session.uid_search(criteria)?.into_iter().collect();
+    uids.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(uids)
+}
+
+/// Select `inbox`, making sure UIDs handed out under `uid_validity` still refer to the same
+/// messages. The server may renumber a mailbox at any time, in which case every UID we hold for it
+/// is meaningless and the list must be reloaded.
+pub fn select_uid_validity(
+    session: &mut ImapSession,
+    inbox: String,
+    uid_validity: u32,
+) -> Result<Mailbox> {
+    let mb = session.select(&inbox)?;
+    if mb.uid_validity != Some(uid_validity) {
+        return Err(Error::Protocol(format!(
+            "UIDVALIDITY of {} changed, reload the mailbox",
+            inbox
+        )));
+    }
+    Ok(mb)
+}
+
+/// The raw RFC 822 message. Fetching it marks the message as read.
+pub fn fetch_body(
+    session: &mut ImapSession,
     inbox: String,
-    n: u32,
-) -> imap::error::Result<Option<Vec<EmailEntry>>> {
-    let mb = session.select(inbox)?;
-    let from = cmp::max(mb.exists, n) - n;
-
-    let query = format!("{}:*", from);
-    let messages = session.fetch(query, "RFC822")?;
-    Ok(Some(
-        messages
-            .iter()
-            .rev()
-            .map(|message| {
-                let body = message.body().expect("message did not have a body!");
-                let body = String::from_utf8_lossy(body).to_string();
-                let msg = mail_parser::MessageParser::new()
-                    .parse(body.as_bytes())
-                    .expect("Failed to parse");
-                let from = msg
-                    .from()
-                    .and_then(|f| f.first())
-                    .and_then(|f| f.name.clone().or(f.address.clone()))
-                    .map(|n| n.to_string())
-                    .unwrap_or_default();
-                let subject = msg.subject().map(|s| s.to_owned()).unwrap_or_default();
-                let subject = subject
-                    .chars()
-                    .filter(|c| c.width().is_some_and(|c| c != 0)) // Remove 0 width chars
-                    .collect();
-                // let date = msg.date().map(|d| d.to_rfc822()).unwrap_or_default();
-                let date = "Mon 12:12 PM".to_owned();
-                let date = msg.keywords().as_text().unwrap_or_default().to_string();
-                // let prio = msg
-                //     .header_values("X-Priority")
-                //     .next()
-                //     .and_then(|x| x.as_text())
-                //     .unwrap_or_default()
-                //     .to_owned();
-                // let date = prio;
-                // let prio = msg
-                //     .headers()
-                //     .iter()
-                //     .rev()
-                //     .find(|h| h.name.to_string().to_lowercase() == "x-priority");
-                // let date = prio
-                //     .and_then(|p| p.value().as_text())
-                //     .map(|x| x.to_owned())
-                //     .unwrap_or_default();
-                EmailEntry {
-                    from,
-                    subject,
-                    date,
-                }
-            })
-            .collect(),
-    ))
-}
-
-pub fn get_html(
-    session: &mut TlsSession,
+    uid: u32,
+    uid_validity: u32,
+) -> Result<Option<Vec<u8>>> {
+    select_uid_validity(session, inbox, uid_validity)?;
+
+    let messages = session.uid_fetch(uid.to_string(), "RFC822")?;
+    let Some(message) = messages.iter().next() else {
+        return Ok(None);
+    };
+    match message.body() {
+        Some(body) => Ok(Some(body.to_vec())),
+        None => Err(Error::Protocol(format!(
+            "the server sent message {} without its body",
+            uid
+        ))),
+    }
+}
+
+/// The message as HTML for the preview, with plain text parts converted. Bodies in charsets
+/// other than UTF-8 are decoded by the parser, so any bytes will do.
+pub fn html_body(body: &[u8]) -> Result<String> {
+    let msg = mail_parser::MessageParser::new()
+        .parse(body)
+        .ok_or(Error::Parse("the message".to_owned()))?;
+    Ok(msg.body_html(0).unwrap_or_default().to_string())
+}
+
+/// Add or remove `flag` on `uids`, returning each message's flags as the server reports them
+/// afterwards.
+pub fn set_flag(
+    session: &mut ImapSession,
     inbox: String,
-    n: u32,
-) -> imap::error::Result<Option<String>> {
-    let mb = session.select(inbox)?;
-    let exists = mb.exists;
-
-    let query = format!("{}", exists - n);
-    let messages = session.fetch(query, "RFC822")?;
-    Ok(Some(
-        messages
-            .iter()
-            .rev()
-            .map(|message| {
-                let body = message.body().expect("message did not have a body!");
-                let body = std::str::from_utf8(body)
-                    .expect("message was not valid utf-8")
-                    .to_string();
-                let msg = mail_parser::MessageParser::new()
-                    .parse(body.as_bytes())
-                    .expect("Failed to parse");
-                msg.body_html(0).unwrap_or_default().to_string()
-            })
-            .next() // TODO: Multiple messages??
-            .unwrap_or_default(),
-    ))
-}
-
-pub fn list_inboxes(s: &mut TlsSession) -> Result<Vec<String>, Box<dyn Error>> {
+    uid_validity: u32,
+    uids: &[u32],
+    flag: MailFlag,
+    add: bool,
+) -> Result<Vec<(u32, Flags)>> {
+    select_uid_validity(session, inbox, uid_validity)?;
+
+    let op = match add {
+        true => "+FLAGS",
+        false => "-FLAGS",
+    };
+    let fetches = session.uid_store(uid_set(uids), format!("{} ({})", op, flag.name()))?;
+    Ok(fetches
+        .iter()
+        .filter_map(|f| Some((f.uid?, Flags::from(f.flags()))))
+        .collect())
+}
+
+/// Permanently remove every message marked `\Deleted` in `inbox`.
+pub fn expunge(session: &mut ImapSession, inbox: String) -> Result<()> {
+    session.select(inbox)?;
+    session.expunge()?;
+    Ok(())
+}
+
+/// Store `message` in `inbox` as already read, like the copy of a message sent.
+pub fn append(session: &mut ImapSession, inbox: &str, message: &[u8]) -> Result<()> {
+    session.append_with_flags(inbox, message, &[Flag::Seen])?;
+    Ok(())
+}
+
+/// RFC 6154 roles a server can advertise for a mailbox.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpecialUse {
+    Archive,
+    Trash,
+    Sent,
+    Drafts,
+    Junk,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Inbox {
+    pub name: String,
+    pub special_use: Option<SpecialUse>,
+}
+
+pub fn list_inboxes(s: &mut ImapSession) -> Result<Vec<Inbox>> {
     let l = s.list(None, Some("*"))?;
-    let inboxes = l.iter().map(|i| i.name().trim().to_owned()).collect();
+    let inboxes = l
+        .iter()
+        .map(|i| Inbox {
+            name: i.name().trim().to_owned(),
+            special_use: i.attributes().iter().find_map(|a| match a {
+                NameAttribute::Custom(a) => match a.to_lowercase().as_str() {
+                    "\\archive" => Some(SpecialUse::Archive),
+                    "\\trash" => Some(SpecialUse::Trash),
+                    "\\sent" => Some(SpecialUse::Sent),
+                    "\\drafts" => Some(SpecialUse::Drafts),
+                    "\\junk" => Some(SpecialUse::Junk),
+                    _ => None,
+                },
+                _ => None,
+            }),
+        })
+        .collect();
     Ok(inboxes)
 }
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Move,
+    Copy,
+}
+
+/// Move or copy `uids` from `inbox` to `dest`. Servers without the MOVE extension get the
+/// equivalent COPY, `\Deleted` and EXPUNGE sequence, using UID EXPUNGE where UIDPLUS allows it so
+/// that other messages marked for deletion are left alone.
+pub fn transfer(
+    session: &mut ImapSession,
+    inbox: String,
+    uid_validity: u32,
+    uids: &[u32],
+    dest: String,
+    op: Transfer,
+) -> Result<()> {
+    select_uid_validity(session, inbox, uid_validity)?;
+
+    let set = uid_set(uids);
+    if op == Transfer::Copy {
+        session.uid_copy(set, dest)?;
+        return Ok(());
+    }
+    let caps = session.capabilities()?;
+    if caps.has_str("MOVE") {
+        session.uid_mv(set, dest)?;
+        return Ok(());
+    }
+    let uidplus = caps.has_str("UIDPLUS");
+    session.uid_copy(&set, dest)?;
+    session.uid_store(&set, "+FLAGS.SILENT (\\Deleted)")?;
+    match uidplus {
+        true => session.uid_expunge(set).map(|_| ())?,
+        false => session.expunge().map(|_| ())?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_vanished_responses() {
+        assert_eq!(
+            vanished("* VANISHED (EARLIER) 41,43:116,120:118"),
+            Some(vec![41..=41, 43..=116, 118..=120])
+        );
+        assert_eq!(vanished("* vanished 405"), Some(vec![405..=405]));
+        assert_eq!(vanished("* ENABLED QRESYNC"), None);
+        assert_eq!(vanished("* VANISHED (EARLIER) 3:x"), None);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{any::Any, fmt, io};
+
+/// What went wrong talking to a server, told apart by what the user can do about it.
+#[derive(Debug)]
+pub enum Error {
+    /// The server couldn't be reached, or the connection broke
+    Network(String),
+    /// The server refused to log in, or credentials couldn't be had
+    Auth(String),
+    /// A message or server response that couldn't be made sense of
+    Parse(String),
+    /// The server refused a command or answered something unexpected
+    Protocol(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network(e) => write!(f, "Network error: {}", e),
+            Error::Auth(e) => write!(f, "Login failed: {}", e),
+            Error::Parse(e) => write!(f, "Couldn't parse {}", e),
+            Error::Protocol(e) => write!(f, "Server error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<imap::Error> for Error {
+    fn from(e: imap::Error) -> Self {
+        match e {
+            imap::Error::Io(_)
+            | imap::Error::ConnectionLost
+            | imap::Error::Tls(_)
+            | imap::Error::TlsHandshake(_) => Error::Network(e.to_string()),
+            imap::Error::Parse(_) => Error::Parse(format!("server response: {}", e)),
+            _ => Error::Protocol(e.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Network(e.to_string())
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(e: native_tls::Error) -> Self {
+        Error::Network(e.to_string())
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for Error {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        match e.status().map(|code| code.to_string()) {
+            // 530, 534 and 535 are about authentication
+            Some(code) if code.starts_with("53") => Error::Auth(e.to_string()),
+            Some(_) => Error::Protocol(e.to_string()),
+            None if e.is_client() => Error::Protocol(e.to_string()),
+            None => Error::Network(e.to_string()),
+        }
+    }
+}
+
+/// The message a thread panicked with, for reporting it as a [`Error::Protocol`], panics in the
+/// background threads coming from the imap crate choking on a response.
+pub fn panicked(payload: Box<dyn Any + Send>) -> Error {
+    let msg = match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(payload) => payload
+            .downcast::<&str>()
+            .map(|s| s.to_string())
+            .unwrap_or_default(),
+    };
+    Error::Protocol(format!("unexpected failure: {}", msg))
+}
@@ -0,0 +1,73 @@
+/// Case-insensitive subsequence match of `pattern` in `text`, returning the char indices of `text`
+/// that matched. An exact substring is preferred over the earliest scattered match, so that `inv`
+/// highlights "invoice" rather than letters spread over "in a very old invoice".
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<Vec<usize>> {
+    let pattern = lowercase(pattern);
+    let text = lowercase(text);
+    if pattern.is_empty() {
+        return Some(vec![]);
+    }
+    if let Some(start) = text.windows(pattern.len()).position(|w| w == pattern) {
+        return Some((start..start + pattern.len()).collect());
+    }
+    let mut indices = Vec::with_capacity(pattern.len());
+    let mut chars = pattern.iter().peekable();
+    for (i, c) in text.iter().enumerate() {
+        if chars.peek() == Some(&c) {
+            indices.push(i);
+            chars.next();
+        }
+    }
+    match chars.peek() {
+        Some(_) => None,
+        None => Some(indices),
+    }
+}
+
+/// Lowercase char by char, so that indices still line up with the original string.
+fn lowercase(s: &str) -> Vec<char> {
+    s.chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_a_subsequence() {
+        assert_eq!(fuzzy_match("ivc", "invoice"), Some(vec![0, 2, 5]));
+    }
+
+    #[test]
+    fn rejects_missing_or_out_of_order_chars() {
+        assert_eq!(fuzzy_match("inx", "invoice"), None);
+        assert_eq!(fuzzy_match("ci", "invoice"), None);
+        assert_eq!(fuzzy_match("invoices", "invoice"), None);
+    }
+
+    #[test]
+    fn ignores_case() {
+        assert_eq!(fuzzy_match("INV", "Invoice"), Some(vec![0, 1, 2]));
+        assert_eq!(fuzzy_match("ä", "BÄR"), Some(vec![1]));
+    }
+
+    #[test]
+    fn prefers_a_contiguous_match_to_an_earlier_scattered_one() {
+        assert_eq!(
+            fuzzy_match("inv", "in a very old invoice"),
+            Some(vec![14, 15, 16])
+        );
+    }
+
+    #[test]
+    fn indices_are_chars_not_bytes() {
+        assert_eq!(fuzzy_match("re", "Größe report"), Some(vec![6, 7]));
+    }
+
+    #[test]
+    fn empty_pattern_matches_anything() {
+        assert_eq!(fuzzy_match("", "anything"), Some(vec![]));
+    }
+}
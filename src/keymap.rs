@@ -0,0 +1,706 @@
+use std::collections::BTreeMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::{app::Mode, widget::Focusable};
+
+/// Something a key can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Help,
+    Quit,
+    NextPane,
+    PrevPane,
+    Filter,
+    Search,
+    Log,
+    Command,
+    Compose,
+    Cancel,
+    Up,
+    Down,
+    First,
+    Last,
+    Select,
+    Close,
+    Mark,
+    ToggleRead,
+    ToggleFlagged,
+    Delete,
+    Undelete,
+    Expunge,
+    Archive,
+    Trash,
+    Move,
+    Copy,
+    Reply,
+    ReplyAll,
+    Forward,
+    ForwardAttached,
+    Submit,
+    LineStart,
+    LineEnd,
+    DeleteWord,
+    ClearLine,
+    Complete,
+}
+
+/// Each action with its name in the config and what it does, for the help.
+const ACTIONS: [(Action, &str, &str); 36] = [
+    (Action::Help, "help", "help"),
+    (Action::Quit, "quit", "quit"),
+    (Action::NextPane, "next-pane", "next pane"),
+    (Action::PrevPane, "prev-pane", "previous pane"),
+    (Action::Filter, "filter", "filter listed messages"),
+    (Action::Search, "search", "search on the server"),
+    (Action::Log, "log", "message log"),
+    (Action::Command, "command", "command line"),
+    (Action::Compose, "compose", "write a message"),
+    (Action::Cancel, "cancel", "cancel loading, clear filter"),
+    (Action::Up, "up", "up"),
+    (Action::Down, "down", "down"),
+    (Action::First, "first", "first"),
+    (Action::Last, "last", "last"),
+    (Action::Select, "select", "select"),
+    (Action::Close, "close", "close"),
+    (Action::Mark, "mark", "mark"),
+    (Action::ToggleRead, "toggle-read", "toggle read"),
+    (Action::ToggleFlagged, "toggle-flagged", "toggle flagged"),
+    (Action::Delete, "delete", "delete"),
+    (Action::Undelete, "undelete", "undelete"),
+    (Action::Expunge, "expunge", "expunge deleted"),
+    (Action::Archive, "archive", "archive"),
+    (Action::Trash, "trash", "trash"),
+    (Action::Move, "move", "move to..."),
+    (Action::Copy, "copy", "copy to..."),
+    (Action::Reply, "reply", "reply"),
+    (Action::ReplyAll, "reply-all", "reply to all"),
+    (Action::Forward, "forward", "forward"),
+    (
+        Action::ForwardAttached,
+        "forward-attached",
+        "forward as attachment",
+    ),
+    (Action::Submit, "submit", "done"),
+    (Action::LineStart, "line-start", "start of line"),
+    (Action::LineEnd, "line-end", "end of line"),
+    (Action::DeleteWord, "delete-word", "delete word"),
+    (Action::ClearLine, "clear-line", "clear"),
+    (Action::Complete, "complete", "complete"),
+];
+
+impl Action {
+    pub fn parse(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|a| a.1 == name).map(|a| a.0)
+    }
+
+    pub fn name(&self) -> &'static str {
+        ACTIONS.iter().find(|a| a.0 == *self).map_or("", |a| a.1)
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        ACTIONS.iter().map(|a| a.1)
+    }
+
+    pub fn description(&self) -> &'static str {
+        ACTIONS.iter().find(|a| a.0 == *self).map_or("", |a| a.2)
+    }
+}
+
+/// Where a binding applies, named as in the `[keys.<scope>]` tables of the config.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Scope {
+    /// Everywhere but the search box and command line, unless the focused widget binds the
+    /// same keys
+    Global,
+    Search,
+    Command,
+    Sidebar,
+    Emails,
+    Preview,
+    Picker,
+    Log,
+    Help,
+    Error,
+}
+
+const SCOPES: [(Scope, &str); 10] = [
+    (Scope::Global, "global"),
+    (Scope::Search, "search"),
+    (Scope::Command, "command"),
+    (Scope::Sidebar, "sidebar"),
+    (Scope::Emails, "emails"),
+    (Scope::Preview, "preview"),
+    (Scope::Picker, "picker"),
+    (Scope::Log, "log"),
+    (Scope::Help, "help"),
+    (Scope::Error, "error"),
+];
+
+impl Scope {
+    /// The scope of whatever `mode` has focused.
+    pub fn of(mode: &Mode) -> Option<Scope> {
+        Some(match mode {
+            Mode::Focus(f) => match f {
+                Focusable::Search => Scope::Search,
+                Focusable::Command => Scope::Command,
+                Focusable::Sidebar => Scope::Sidebar,
+                Focusable::Emails => Scope::Emails,
+                Focusable::Preview => Scope::Preview,
+                Focusable::Picker(_) => Scope::Picker,
+                Focusable::Log => Scope::Log,
+                Focusable::Help => Scope::Help,
+            },
+            Mode::Error(_) => Scope::Error,
+            Mode::Loading(_) => return None,
+        })
+    }
+
+    fn parse(name: &str) -> Option<Scope> {
+        SCOPES.iter().find(|s| s.1 == name).map(|s| s.0)
+    }
+
+    fn name(&self) -> &'static str {
+        SCOPES.iter().find(|s| s.0 == *self).map_or("", |s| s.1)
+    }
+
+    /// The actions bindable here: those of the scope, and the global ones where keys aren't
+    /// typed as text.
+    fn actions(&self) -> Vec<Action> {
+        let mut defaults = self.defaults().to_vec();
+        if !self.is_text() && *self != Scope::Global {
+            defaults.extend(Scope::Global.defaults());
+        }
+        let mut actions = vec![];
+        for (_, action) in defaults {
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+        actions
+    }
+
+    /// Whether keys bound to nothing are typed as text, global bindings not applying.
+    fn is_text(&self) -> bool {
+        matches!(self, Scope::Search | Scope::Command)
+    }
+
+    /// Keys bound unless the config says otherwise. The actions listed are the only ones the
+    /// scope knows what to do with.
+    fn defaults(&self) -> &'static [(&'static str, Action)] {
+        match self {
+            Scope::Global => &[
+                ("?", Action::Help),
+                ("q", Action::Quit),
+                ("<Tab>", Action::NextPane),
+                ("l", Action::NextPane),
+                ("<S-Tab>", Action::PrevPane),
+                ("h", Action::PrevPane),
+                ("/", Action::Filter),
+                ("s", Action::Search),
+                ("L", Action::Log),
+                (":", Action::Command),
+                ("C", Action::Compose),
+                ("<Esc>", Action::Cancel),
+            ],
+            Scope::Search => &[
+                ("<Enter>", Action::Submit),
+                ("<Esc>", Action::Close),
+                ("<C-a>", Action::LineStart),
+                ("<C-e>", Action::LineEnd),
+                ("<C-w>", Action::DeleteWord),
+                ("<C-u>", Action::ClearLine),
+            ],
+            Scope::Command => &[
+                ("<Enter>", Action::Submit),
+                ("<Esc>", Action::Close),
+                ("<Tab>", Action::Complete),
+                ("<Up>", Action::Up),
+                ("<C-p>", Action::Up),
+                ("<Down>", Action::Down),
+                ("<C-n>", Action::Down),
+                ("<C-a>", Action::LineStart),
+                ("<C-e>", Action::LineEnd),
+                ("<C-w>", Action::DeleteWord),
+                ("<C-u>", Action::ClearLine),
+            ],
+            Scope::Sidebar => &[
+                ("k", Action::Up),
+                ("j", Action::Down),
+                ("<Enter>", Action::Select),
+            ],
+            Scope::Emails => &[
+                ("k", Action::Up),
+                ("j", Action::Down),
+                ("g", Action::First),
+                ("G", Action::Last),
+                ("<Enter>", Action::Select),
+                ("<Space>", Action::Select),
+                ("v", Action::Mark),
+                ("r", Action::ToggleRead),
+                ("f", Action::ToggleFlagged),
+                ("d", Action::Delete),
+                ("u", Action::Undelete),
+                ("$", Action::Expunge),
+                ("a", Action::Archive),
+                ("t", Action::Trash),
+                ("m", Action::Move),
+                ("c", Action::Copy),
+                ("R", Action::Reply),
+                ("A", Action::ReplyAll),
+                ("F", Action::Forward),
+                ("<C-f>", Action::ForwardAttached),
+            ],
+            Scope::Preview => &[
+                ("k", Action::Up),
+                ("j", Action::Down),
+                ("R", Action::Reply),
+                ("A", Action::ReplyAll),
+                ("F", Action::Forward),
+                ("<C-f>", Action::ForwardAttached),
+            ],
+            Scope::Picker => &[
+                ("k", Action::Up),
+                ("j", Action::Down),
+                ("g", Action::First),
+                ("G", Action::Last),
+                ("<Enter>", Action::Select),
+                ("<Esc>", Action::Close),
+            ],
+            Scope::Log => &[
+                ("k", Action::Up),
+                ("j", Action::Down),
+                ("g", Action::First),
+                ("G", Action::Last),
+                ("<Esc>", Action::Close),
+            ],
+            Scope::Help => &[
+                ("k", Action::Up),
+                ("j", Action::Down),
+                ("<Esc>", Action::Close),
+                ("?", Action::Close),
+            ],
+            Scope::Error => &[("<Enter>", Action::Close), ("<Esc>", Action::Close)],
+        }
+    }
+}
+
+/// A key with the modifiers held, as far as they matter: shift is part of the character.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+/// Names of the keys written in angle brackets.
+const KEY_NAMES: [(KeyCode, &str); 16] = [
+    (KeyCode::Enter, "Enter"),
+    (KeyCode::Esc, "Esc"),
+    (KeyCode::Tab, "Tab"),
+    (KeyCode::Backspace, "BS"),
+    (KeyCode::Delete, "Del"),
+    (KeyCode::Insert, "Ins"),
+    (KeyCode::Char(' '), "Space"),
+    (KeyCode::Char('<'), "lt"),
+    (KeyCode::Up, "Up"),
+    (KeyCode::Down, "Down"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::Home, "Home"),
+    (KeyCode::End, "End"),
+    (KeyCode::PageUp, "PageUp"),
+    (KeyCode::PageDown, "PageDown"),
+];
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key {
+            code,
+            modifiers: modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT),
+        }
+    }
+
+    /// Parse a sequence like `gg`, `<C-d>` or `<S-Tab>`, vim style.
+    pub fn parse_sequence(s: &str) -> Result<Vec<Key>, String> {
+        let mut keys = vec![];
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            let bracketed = match c {
+                '<' => rest.find('>').filter(|&end| end > 1),
+                _ => None,
+            };
+            match bracketed {
+                Some(end) => {
+                    keys.push(Key::parse_bracketed(&rest[1..end]).ok_or(format!(
+                        "unknown key \"{}\" in \"{}\"",
+                        &rest[..=end],
+                        s
+                    ))?);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    keys.push(Key::new(KeyCode::Char(c), KeyModifiers::empty()));
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        match keys.is_empty() {
+            true => Err("empty key sequence".to_owned()),
+            false => Ok(keys),
+        }
+    }
+
+    /// The inside of `<...>`: modifiers like `C-` and `A-`, then a key name or a character.
+    fn parse_bracketed(s: &str) -> Option<Key> {
+        let mut modifiers = KeyModifiers::empty();
+        let mut shift = false;
+        let mut name = s;
+        while name.len() > 2 && name.as_bytes()[1] == b'-' {
+            match name.as_bytes()[0].to_ascii_uppercase() {
+                b'C' => modifiers |= KeyModifiers::CONTROL,
+                b'A' | b'M' => modifiers |= KeyModifiers::ALT,
+                b'S' => shift = true,
+                _ => return None,
+            }
+            name = &name[2..];
+        }
+        let named = KEY_NAMES
+            .iter()
+            .find(|k| k.1.eq_ignore_ascii_case(name))
+            .map(|k| k.0);
+        let code = match (named, name.chars().count()) {
+            (Some(code), _) => code,
+            (None, 1) => KeyCode::Char(name.chars().next()?),
+            (None, _) => match name.strip_prefix(['F', 'f']).map(str::parse) {
+                Some(Ok(n)) => KeyCode::F(n),
+                _ => return None,
+            },
+        };
+        Some(match (code, shift) {
+            (KeyCode::Tab, true) => Key::new(KeyCode::BackTab, modifiers),
+            (KeyCode::Char(c), true) => Key::new(KeyCode::Char(c.to_ascii_uppercase()), modifiers),
+            (_, true) => return None,
+            (code, false) => Key::new(code, modifiers),
+        })
+    }
+}
+
+impl From<&KeyEvent> for Key {
+    fn from(e: &KeyEvent) -> Self {
+        Key::new(e.code, e.modifiers)
+    }
+}
+
+/// Show `keys` the way they are written in the config.
+pub fn format_sequence(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|k| {
+            let name = KEY_NAMES.iter().find(|n| n.0 == k.code).map(|n| n.1);
+            let name = match (k.code, name) {
+                (_, Some(name)) => name.to_owned(),
+                (KeyCode::Char(c), None) => c.to_string(),
+                (KeyCode::F(n), None) => format!("F{}", n),
+                (code, None) => format!("{:?}", code),
+            };
+            let mut prefix = String::new();
+            if k.modifiers.contains(KeyModifiers::CONTROL) {
+                prefix.push_str("C-");
+            }
+            if k.modifiers.contains(KeyModifiers::ALT) {
+                prefix.push_str("A-");
+            }
+            match (k.code, prefix.is_empty(), name.chars().count()) {
+                (KeyCode::BackTab, ..) => format!("<S-{}Tab>", prefix),
+                (_, true, 1) => name,
+                _ => format!("<{}{}>", prefix, name),
+            }
+        })
+        .collect()
+}
+
+/// What a key press amounts to.
+pub enum Resolved {
+    Action(Action),
+    /// The start of a longer sequence, waiting for the rest
+    Pending,
+    /// Nothing, left for the widget to take as typed text
+    Unbound,
+}
+
+type Bindings = Vec<(Vec<Key>, Action)>;
+
+/// Key sequences bound to actions, per scope.
+pub struct Keymap {
+    bindings: BTreeMap<Scope, Bindings>,
+    /// Keys of a sequence typed so far
+    pending: Vec<Key>,
+}
+
+impl Keymap {
+    /// The default bindings, changed by the `[keys.<scope>]` tables of the config mapping key
+    /// sequences to action names. `"none"` unbinds a sequence.
+    pub fn new(config: &BTreeMap<String, BTreeMap<String, String>>) -> Result<Keymap, String> {
+        let mut bindings = BTreeMap::new();
+        for (scope, _) in SCOPES {
+            let defaults = scope.defaults().iter().map(|(keys, action)| {
+                Key::parse_sequence(keys)
+                    .map(|keys| (keys, *action))
+                    .map_err(|e| format!("default binding: {}", e))
+            });
+            bindings.insert(scope, defaults.collect::<Result<Bindings, String>>()?);
+        }
+        for (name, table) in config {
+            let scope = Scope::parse(name).ok_or(format!(
+                "unknown key scope [keys.{}], expected one of: {}",
+                name,
+                SCOPES.map(|s| s.1).join(", ")
+            ))?;
+            let scoped = bindings.entry(scope).or_default();
+            for (keys, action) in table {
+                let seq = Key::parse_sequence(keys)
+                    .map_err(|e| format!("[keys.{}] \"{}\": {}", name, keys, e))?;
+                scoped.retain(|(k, _)| *k != seq);
+                if action == "none" {
+                    continue;
+                }
+                let action = Action::parse(action)
+                    .filter(|a| scope.actions().contains(a))
+                    .ok_or(format!(
+                        "[keys.{}] \"{}\": no action \"{}\" here, expected one of: {}",
+                        name,
+                        keys,
+                        action,
+                        scope
+                            .actions()
+                            .iter()
+                            .map(Action::name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))?;
+                scoped.push((seq, action));
+            }
+        }
+        let keymap = Keymap {
+            bindings,
+            pending: vec![],
+        };
+        keymap.check_prefixes()?;
+        Ok(keymap)
+    }
+
+    /// A sequence that starts another one could never be completed, or the other never reached.
+    fn check_prefixes(&self) -> Result<(), String> {
+        for (scope, _) in SCOPES {
+            let bindings = self.visible(scope);
+            for (a, _) in &bindings {
+                for (b, _) in &bindings {
+                    if a.len() < b.len() && b.starts_with(a) {
+                        return Err(format!(
+                            "key \"{}\" of [keys.{}] is the start of \"{}\", so one of them can't \
+                             be used",
+                            format_sequence(a),
+                            scope.name(),
+                            format_sequence(b)
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The bindings in effect in `scope`: its own, then the global ones it doesn't override.
+    fn visible(&self, scope: Scope) -> Vec<(&[Key], Action)> {
+        let own = self.bindings.get(&scope).into_iter().flatten();
+        let mut visible: Vec<_> = own.map(|(k, a)| (k.as_slice(), *a)).collect();
+        if !scope.is_text() && scope != Scope::Global {
+            let global = self.bindings.get(&Scope::Global).into_iter().flatten();
+            let global: Vec<_> = global
+                .filter(|(k, _)| !visible.iter().any(|(v, _)| v == k))
+                .map(|(k, a)| (k.as_slice(), *a))
+                .collect();
+            visible.extend(global);
+        }
+        visible
+    }
+
+    /// Take the key `e` pressed in `scope`, which completes a sequence or continues one.
+    pub fn resolve(&mut self, scope: Scope, e: &KeyEvent) -> Resolved {
+        self.pending.push(Key::from(e));
+        let visible = self.visible(scope);
+        if let Some((_, action)) = visible.iter().find(|(k, _)| *k == self.pending) {
+            let action = *action;
+            self.pending.clear();
+            return Resolved::Action(action);
+        }
+        if visible.iter().any(|(k, _)| k.starts_with(&self.pending)) {
+            return Resolved::Pending;
+        }
+        // A sequence gone wrong starts over from the key that broke it
+        let restart = self.pending.len() > 1;
+        self.pending.clear();
+        match restart {
+            true => self.resolve(scope, e),
+            false => Resolved::Unbound,
+        }
+    }
+
+    /// Keys of an unfinished sequence.
+    pub fn pending(&self) -> String {
+        format_sequence(&self.pending)
+    }
+
+    /// Keys and descriptions of `actions` in `scope`, for those that have keys.
+    pub fn describe(&self, scope: Scope, actions: &[Action]) -> Vec<(String, &'static str)> {
+        let visible = self.visible(scope);
+        actions
+            .iter()
+            .filter_map(|action| {
+                let keys: Vec<String> = visible
+                    .iter()
+                    .filter(|(_, a)| a == action)
+                    .map(|(k, _)| format_sequence(k))
+                    .collect();
+                match keys.is_empty() {
+                    true => None,
+                    false => Some((keys.join("/"), action.description())),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+        scope: &str,
+        bindings: &[(&str, &str)],
+    ) -> BTreeMap<String, BTreeMap<String, String>> {
+        let table = bindings
+            .iter()
+            .map(|(k, a)| (k.to_string(), a.to_string()))
+            .collect();
+        BTreeMap::from([(scope.to_owned(), table)])
+    }
+
+    fn press(
+        keymap: &mut Keymap,
+        scope: Scope,
+        code: KeyCode,
+        modifiers: KeyModifiers,
+    ) -> Resolved {
+        keymap.resolve(scope, &KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn parses_plain_and_bracketed_keys() {
+        let keys = Key::parse_sequence("g<C-d><S-Tab><lt><F5>").unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                Key::new(KeyCode::Char('g'), KeyModifiers::empty()),
+                Key::new(KeyCode::Char('d'), KeyModifiers::CONTROL),
+                Key::new(KeyCode::BackTab, KeyModifiers::empty()),
+                Key::new(KeyCode::Char('<'), KeyModifiers::empty()),
+                Key::new(KeyCode::F(5), KeyModifiers::empty()),
+            ]
+        );
+        assert_eq!(format_sequence(&keys), "g<C-d><S-Tab><lt><F5>");
+    }
+
+    #[test]
+    fn shift_uppercases_and_is_dropped_from_chars() {
+        assert_eq!(
+            Key::parse_sequence("<S-a>").unwrap(),
+            Key::parse_sequence("A").unwrap()
+        );
+        assert_eq!(
+            Key::new(KeyCode::Char('A'), KeyModifiers::SHIFT),
+            Key::new(KeyCode::Char('A'), KeyModifiers::empty())
+        );
+    }
+
+    #[test]
+    fn lone_brackets_are_characters() {
+        assert_eq!(
+            Key::parse_sequence("<>").unwrap(),
+            vec![
+                Key::new(KeyCode::Char('<'), KeyModifiers::empty()),
+                Key::new(KeyCode::Char('>'), KeyModifiers::empty()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_empty_keys() {
+        assert!(Key::parse_sequence("<Nope>").is_err());
+        assert!(Key::parse_sequence("<X-a>").is_err());
+        assert!(Key::parse_sequence("<S-Enter>").is_err());
+        assert!(Key::parse_sequence("").is_err());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Keymap::new(&BTreeMap::new()).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_scopes_and_actions() {
+        assert!(Keymap::new(&config("nowhere", &[("x", "quit")])).is_err());
+        assert!(Keymap::new(&config("emails", &[("x", "launch")])).is_err());
+        // An action of another scope
+        assert!(Keymap::new(&config("sidebar", &[("x", "reply")])).is_err());
+    }
+
+    #[test]
+    fn rejects_a_sequence_starting_another() {
+        let err = Keymap::new(&config("emails", &[("gx", "last")]))
+            .err()
+            .unwrap();
+        assert!(err.contains("\"g\""), "{}", err);
+        // Also against the global bindings the scope sees
+        assert!(Keymap::new(&config("emails", &[("qq", "last")])).is_err());
+    }
+
+    #[test]
+    fn resolves_sequences_once_the_prefix_is_unbound() {
+        let config = config("emails", &[("g", "none"), ("gg", "first")]);
+        let mut keymap = Keymap::new(&config).unwrap();
+        let none = KeyModifiers::empty();
+        assert!(matches!(
+            press(&mut keymap, Scope::Emails, KeyCode::Char('g'), none),
+            Resolved::Pending
+        ));
+        assert_eq!(keymap.pending(), "g");
+        assert!(matches!(
+            press(&mut keymap, Scope::Emails, KeyCode::Char('g'), none),
+            Resolved::Action(Action::First)
+        ));
+        // A wrong key starts over from itself
+        press(&mut keymap, Scope::Emails, KeyCode::Char('g'), none);
+        assert!(matches!(
+            press(&mut keymap, Scope::Emails, KeyCode::Char('j'), none),
+            Resolved::Action(Action::Down)
+        ));
+        assert!(matches!(
+            press(&mut keymap, Scope::Emails, KeyCode::Char('z'), none),
+            Resolved::Unbound
+        ));
+    }
+
+    #[test]
+    fn scope_bindings_override_global_ones() {
+        let mut keymap = Keymap::new(&config("emails", &[("q", "last")])).unwrap();
+        let none = KeyModifiers::empty();
+        assert!(matches!(
+            press(&mut keymap, Scope::Emails, KeyCode::Char('q'), none),
+            Resolved::Action(Action::Last)
+        ));
+        assert!(matches!(
+            press(&mut keymap, Scope::Sidebar, KeyCode::Char('q'), none),
+            Resolved::Action(Action::Quit)
+        ));
+    }
+}
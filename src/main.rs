@@ -1,23 +1,42 @@
-use std::{env, io::stdout};
+use std::{
+    env,
+    io::{self, stdout},
+    panic, thread,
+};
 
 use app::App;
+use config::Config;
 use crossterm::{
-    cursor::SetCursorStyle,
+    cursor::{SetCursorStyle, Show},
     event::{DisableMouseCapture, EnableMouseCapture},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
+use keymap::Keymap;
 use ratatui::{backend::CrosstermBackend, Terminal};
 
 mod app;
+mod cache;
+mod command;
+mod compose;
 mod config;
+mod connection;
+mod credentials;
 mod email;
+mod error;
+mod fuzzy;
+mod keymap;
+mod oauth;
+mod query;
+mod smtp;
+mod watcher;
 mod widget;
+mod worker;
 
 #[tokio::main()]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
-    for arg in args {
+    for (i, arg) in args.iter().enumerate() {
         if arg == "--version" || arg == "-V" || arg == "-v" {
             println!(
                 "tinbox v{}",
@@ -25,23 +44,52 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             );
             return Ok(());
         }
+        if arg == "--store-password" {
+            let Some(account) = args.get(i + 1) else {
+                return Err("Usage: tinbox --store-password <account name>".into());
+            };
+            credentials::store_password(account)?;
+            return Ok(());
+        }
     }
-    enable_raw_mode()?;
-    stdout().execute(EnterAlternateScreen)?;
-    stdout().execute(SetCursorStyle::SteadyBar)?;
-    stdout().execute(EnableMouseCapture)?;
+    let mut conf = Config::load()?;
+    conf.resolve_passwords()?;
+    let keymap = Keymap::new(&conf.keys).map_err(|e| format!("Invalid key bindings: {}", e))?;
+    // Panics in the background threads are reported as errors by them, the app carries on
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if thread::current().name() == Some("main") {
+            let _ = restore_terminal();
+            default_hook(info);
+        }
+    }));
+    setup_terminal()?;
     let backend = CrosstermBackend::new(stdout());
     let mut terminal = Terminal::new(backend)?;
 
     let mut app = App::default();
 
-    app.run_app(&mut terminal).await?;
+    let result = app.run_app(&mut terminal, conf, keymap).await;
+
+    restore_terminal()?;
+    result
+}
 
+/// Take over the terminal for drawing the app.
+fn setup_terminal() -> io::Result<()> {
+    enable_raw_mode()?;
+    stdout().execute(EnterAlternateScreen)?;
+    stdout().execute(SetCursorStyle::SteadyBar)?;
+    stdout().execute(EnableMouseCapture)?;
+    Ok(())
+}
+
+/// Leave the terminal the way it was found.
+fn restore_terminal() -> io::Result<()> {
     disable_raw_mode()?;
     stdout().execute(DisableMouseCapture)?;
     stdout().execute(SetCursorStyle::DefaultUserShape)?;
     stdout().execute(LeaveAlternateScreen)?;
-    terminal.show_cursor()?;
-
+    stdout().execute(Show)?;
     Ok(())
 }
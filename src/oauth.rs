@@ -0,0 +1,207 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use imap::{Authenticator, Client};
+use serde::Deserialize;
+
+use crate::{
+    config::{self, Account, Mechanism, OAuth},
+    connection::Stream,
+    credentials,
+    email::ImapSession,
+    error::{Error, Result},
+};
+
+/// Tokens are refreshed this long before they expire, so one doesn't run out mid-login.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+struct Token {
+    access: String,
+    /// Unknown for tokens printed by a command, those are used until the server refuses them
+    expires: Option<Instant>,
+    /// Handed out by servers that rotate refresh tokens, replacing the configured one
+    refresh: Option<String>,
+}
+
+/// Access tokens by account name, shared by the worker and watcher connections of each.
+static TOKENS: Mutex<BTreeMap<String, Token>> = Mutex::new(BTreeMap::new());
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+    refresh_token: Option<String>,
+}
+
+/// SASL XOAUTH2, as spoken by Gmail and Microsoft 365.
+struct XOAuth2<'a> {
+    user: &'a str,
+    token: &'a str,
+}
+
+impl Authenticator for XOAuth2<'_> {
+    type Response = String;
+    fn process(&self, challenge: &[u8]) -> String {
+        // A challenge after the first response carries the error, answered empty to get the NO
+        if !challenge.is_empty() {
+            return String::new();
+        }
+        format!("user={}\x01auth=Bearer {}\x01\x01", self.user, self.token)
+    }
+}
+
+/// SASL OAUTHBEARER (RFC 7628).
+struct OAuthBearer<'a> {
+    user: &'a str,
+    token: &'a str,
+    host: &'a str,
+    port: u16,
+}
+
+impl Authenticator for OAuthBearer<'_> {
+    type Response = String;
+    fn process(&self, challenge: &[u8]) -> String {
+        if !challenge.is_empty() {
+            return "\x01".to_owned();
+        }
+        format!(
+            "n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+            self.user, self.host, self.port, self.token
+        )
+    }
+}
+
+/// Log in to `account` with an OAuth2 access token. A refused token is dropped and a fresh one
+/// tried once, since the server may consider it expired before we do.
+pub fn authenticate(
+    mut client: Client<Stream>,
+    account: &Account,
+    oauth: &OAuth,
+) -> Result<ImapSession> {
+    let mut retried = false;
+    loop {
+        let token = access_token(account, oauth)?;
+        let result = match oauth.mechanism {
+            Mechanism::XOAuth2 => {
+                let auth = XOAuth2 {
+                    user: &account.username,
+                    token: &token,
+                };
+                client.authenticate("XOAUTH2", &auth)
+            }
+            Mechanism::OAuthBearer => {
+                let auth = OAuthBearer {
+                    user: &account.username,
+                    token: &token,
+                    host: &account.imap_server,
+                    port: account.imap_port,
+                };
+                client.authenticate("OAUTHBEARER", &auth)
+            }
+        };
+        match result {
+            Ok(session) => return Ok(session),
+            Err((imap::Error::No(_), c)) if !retried => {
+                invalidate(account);
+                client = c;
+                retried = true;
+            }
+            Err((imap::Error::No(e), _)) => return Err(Error::Auth(e)),
+            Err((e, _)) => return Err(e.into()),
+        }
+    }
+}
+
+/// The access token of `account`, reusing the last one until shortly before it expires. The
+/// tokens aren't locked while a new one is fetched, which may take as long as the network or
+/// token command does, so other accounts' connections can go on logging in meanwhile.
+pub fn access_token(account: &Account, oauth: &OAuth) -> Result<String> {
+    let lock = || TOKENS.lock().map_err(|e| Error::Auth(e.to_string()));
+    let refresh = {
+        let tokens = lock()?;
+        let current = tokens.get(&account.name);
+        let fresh =
+            current.is_some_and(|t| t.expires.is_none_or(|e| Instant::now() + EXPIRY_MARGIN < e));
+        if let (true, Some(t)) = (fresh, current) {
+            return Ok(t.access.to_owned());
+        }
+        current.and_then(|t| t.refresh.to_owned())
+    };
+    let token = fetch(account, oauth, refresh)?;
+    let access = token.access.to_owned();
+    lock()?.insert(account.name.to_owned(), token);
+    Ok(access)
+}
+
+/// Forget the access token of `account`, keeping any rotated refresh token.
+fn invalidate(account: &Account) {
+    if let Ok(mut tokens) = TOKENS.lock() {
+        if let Some(t) = tokens.get_mut(&account.name) {
+            t.expires = Some(Instant::now());
+        }
+    }
+}
+
+/// A new access token, from the token command or else by refreshing at the token endpoint with
+/// the account's password as refresh token.
+fn fetch(account: &Account, oauth: &OAuth, refresh: Option<String>) -> Result<Token> {
+    if let Some(cmd) = &oauth.token_command {
+        let access = config::run_secret_command(cmd, false)
+            .map_err(|e| Error::Auth(format!("oauth token_command failed: {}", e)))?;
+        return Ok(Token {
+            access,
+            expires: None,
+            refresh,
+        });
+    }
+    let url = oauth.token_url.as_deref().ok_or(Error::Auth(
+        "OAuth needs either token_command or token_url".to_owned(),
+    ))?;
+    let refresh = refresh.unwrap_or(account.password.to_owned());
+    if refresh.is_empty() {
+        return Err(Error::Auth(
+            "no refresh token, store it as the account's password".to_owned(),
+        ));
+    }
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", &refresh),
+        ("client_id", &oauth.client_id),
+    ];
+    if let Some(secret) = &oauth.client_secret {
+        form.push(("client_secret", secret));
+    }
+    let response: TokenResponse = match ureq::post(url).send_form(&form) {
+        Ok(r) => r
+            .into_json()
+            .map_err(|e| Error::Parse(format!("the token response: {}", e)))?,
+        Err(ureq::Error::Status(code, r)) => {
+            let body = r.into_string().unwrap_or_default();
+            return Err(Error::Auth(format!(
+                "refreshing the access token failed ({}): {}",
+                code, body
+            )));
+        }
+        Err(e) => {
+            return Err(Error::Network(format!(
+                "refreshing the access token failed: {}",
+                e
+            )))
+        }
+    };
+    // The old one may stop working right away, so the next launch needs the new one. Failing to
+    // write it is no reason to fail now, with the new one at hand until quitting.
+    if let Some(rotated) = &response.refresh_token {
+        let _ = credentials::update(&account.name, rotated);
+    }
+    Ok(Token {
+        access: response.access_token,
+        expires: response
+            .expires_in
+            .map(|s| Instant::now() + Duration::from_secs(s)),
+        refresh: response.refresh_token.or(Some(refresh)),
+    })
+}
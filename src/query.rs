@@ -0,0 +1,155 @@
+use chrono::NaiveDate;
+
+/// Translate a search box query into IMAP `SEARCH` criteria.
+///
+/// Terms are separated by whitespace and may be quoted to include spaces. `key:value` terms are
+/// `from:`, `to:`, `subject:`, `since:` and `before:` (dates as `YYYY-MM-DD`), `is:` (`unread`,
+/// `read`, `flagged`, `answered` or `deleted`) and `has:attachment`. Anything else is matched
+/// against the whole message text.
+pub fn to_criteria(query: &str) -> Result<String, String> {
+    let mut criteria = vec![];
+    for term in split_terms(query) {
+        let (key, value) = match term.split_once(':') {
+            Some((key, value)) if !value.is_empty() => (key.to_lowercase(), value),
+            _ => {
+                criteria.push(criteria_text(&term));
+                continue;
+            }
+        };
+        criteria.push(match key.as_str() {
+            "from" => format!("FROM {}", quote(value)),
+            "to" => format!("TO {}", quote(value)),
+            "subject" => format!("SUBJECT {}", quote(value)),
+            "since" => format!("SINCE {}", date(value)?),
+            "before" => format!("BEFORE {}", date(value)?),
+            "is" => match value.to_lowercase().as_str() {
+                "unread" => "UNSEEN",
+                "read" => "SEEN",
+                "flagged" | "starred" => "FLAGGED",
+                "answered" => "ANSWERED",
+                "deleted" => "DELETED",
+                _ => return Err(format!("Unknown search term is:{}", value)),
+            }
+            .to_owned(),
+            // Same guess as the attachment indicator in the message list
+            "has" if value.eq_ignore_ascii_case("attachment") => {
+                "HEADER Content-Type \"multipart/mixed\"".to_owned()
+            }
+            _ => criteria_text(&term),
+        });
+    }
+    if criteria.is_empty() {
+        return Ok("ALL".to_owned());
+    }
+    let criteria = criteria.join(" ");
+    match criteria.is_ascii() {
+        true => Ok(criteria),
+        false => Ok(format!("CHARSET UTF-8 {}", criteria)),
+    }
+}
+
+fn criteria_text(term: &str) -> String {
+    format!("TEXT {}", quote(term))
+}
+
+/// Split on whitespace, keeping double quoted sections (which may appear mid term, as in
+/// `subject:"hello world"`) together.
+fn split_terms(query: &str) -> Vec<String> {
+    let mut terms = vec![];
+    let mut term = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !term.is_empty() {
+                    terms.push(std::mem::take(&mut term));
+                }
+            }
+            c => term.push(c),
+        }
+    }
+    if !term.is_empty() {
+        terms.push(term);
+    }
+    terms
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn date(s: &str) -> Result<String, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|d| d.format("%-d-%b-%Y").to_string())
+        .map_err(|_| format!("Invalid date \"{}\", expected YYYY-MM-DD", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_query_matches_all() {
+        assert_eq!(to_criteria("  ").unwrap(), "ALL");
+    }
+
+    #[test]
+    fn bare_words_search_the_text() {
+        assert_eq!(
+            to_criteria("hello world").unwrap(),
+            "TEXT \"hello\" TEXT \"world\""
+        );
+    }
+
+    #[test]
+    fn quotes_keep_spaces_together() {
+        assert_eq!(
+            to_criteria("\"hello world\" subject:\"weekly report\"").unwrap(),
+            "TEXT \"hello world\" SUBJECT \"weekly report\""
+        );
+    }
+
+    #[test]
+    fn backslashes_are_escaped() {
+        assert_eq!(to_criteria(r"from:a\b").unwrap(), r#"FROM "a\\b""#);
+    }
+
+    #[test]
+    fn keys_are_case_insensitive() {
+        assert_eq!(
+            to_criteria("FROM:alice Is:Unread has:Attachment").unwrap(),
+            "FROM \"alice\" UNSEEN HEADER Content-Type \"multipart/mixed\""
+        );
+    }
+
+    #[test]
+    fn unknown_keys_and_empty_values_search_the_text() {
+        assert_eq!(
+            to_criteria("foo:bar to:").unwrap(),
+            "TEXT \"foo:bar\" TEXT \"to:\""
+        );
+    }
+
+    #[test]
+    fn dates_are_in_imap_form() {
+        assert_eq!(
+            to_criteria("since:2024-03-05 before:2024-12-25").unwrap(),
+            "SINCE 5-Mar-2024 BEFORE 25-Dec-2024"
+        );
+        assert!(to_criteria("since:yesterday").is_err());
+    }
+
+    #[test]
+    fn unknown_flag_is_an_error() {
+        assert!(to_criteria("is:important").is_err());
+    }
+
+    #[test]
+    fn non_ascii_needs_a_charset() {
+        assert_eq!(
+            to_criteria("subject:grüße").unwrap(),
+            "CHARSET UTF-8 SUBJECT \"grüße\""
+        );
+    }
+}
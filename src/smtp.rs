@@ -0,0 +1,202 @@
+use std::{fs, time::Duration};
+
+use lettre::{
+    transport::smtp::{
+        authentication::{Credentials, Mechanism, DEFAULT_MECHANISMS},
+        client::{Certificate, Identity, SmtpConnection, TlsParameters},
+        extension::ClientId,
+    },
+    Message,
+};
+
+use crate::{
+    config::{Account, Security, Smtp},
+    connection::{check_fingerprint, pem_certificates, read_error},
+    error::{Error, Result},
+    oauth,
+};
+
+/// How long any one step of talking to the server may take.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Hand `message` to the SMTP server of `account` for delivery.
+pub fn send(account: &Account, message: &Message) -> Result<()> {
+    let smtp = account.smtp.as_ref().ok_or(Error::Protocol(
+        "no SMTP server, set [smtp] for the account in config".to_owned(),
+    ))?;
+    let mut conn = connect(account, smtp)?;
+    let sent = conn.send(message.envelope(), &message.formatted());
+    // The message is the server's once it accepted it, however the goodbye goes
+    let _ = conn.quit();
+    sent?;
+    Ok(())
+}
+
+/// A connection to `smtp` as its `security` says, secured with the account's CA file, client
+/// certificate and pinned fingerprint like IMAP is, and logged in like the account is there.
+/// Without a password or OAuth it doesn't log in at all, which local stand-in servers accept.
+fn connect(account: &Account, smtp: &Smtp) -> Result<SmtpConnection> {
+    let server = smtp.server.as_str();
+    let (params, port) = match smtp.security {
+        Security::Tls => (Some(tls_parameters(account, server)?), 465),
+        Security::StartTls => (Some(tls_parameters(account, server)?), 587),
+        Security::None => (None, 25),
+    };
+    let port = if smtp.port == 0 { port } else { smtp.port };
+    let hello = ClientId::default();
+    let wrapper = params
+        .as_ref()
+        .filter(|_| matches!(smtp.security, Security::Tls));
+    let mut conn = SmtpConnection::connect((server, port), Some(TIMEOUT), &hello, wrapper, None)?;
+    if let (Security::StartTls, Some(params)) = (smtp.security, &params) {
+        conn.starttls(params, &hello)?;
+    }
+    // Before sending any credentials
+    if let (Some(pin), Some(_)) = (&account.cert_fingerprint, &params) {
+        check_fingerprint(server, pin, &conn.peer_certificate()?)?;
+    }
+    let username = smtp
+        .username
+        .to_owned()
+        .unwrap_or(account.username.to_owned());
+    if let Some(o) = &account.oauth {
+        // Gmail and Microsoft 365 both take XOAUTH2 for SMTP, whatever IMAP uses
+        let token = oauth::access_token(account, o)?;
+        conn.auth(&[Mechanism::Xoauth2], &Credentials::new(username, token))?;
+    } else if !account.password.is_empty() {
+        let credentials = Credentials::new(username, account.password.to_owned());
+        conn.auth(DEFAULT_MECHANISMS, &credentials)?;
+    }
+    Ok(conn)
+}
+
+fn tls_parameters(account: &Account, server: &str) -> Result<TlsParameters> {
+    let mut params = TlsParameters::builder(server.to_owned());
+    if let Some(path) = &account.ca_file {
+        let pem = fs::read_to_string(path).map_err(|e| read_error(path, e))?;
+        for cert in pem_certificates(&pem) {
+            params = params.add_root_certificate(Certificate::from_pem(cert.as_bytes())?);
+        }
+    }
+    if let (Some(cert), Some(key)) = (&account.client_cert, &account.client_key) {
+        let cert = fs::read(cert).map_err(|e| read_error(cert, e))?;
+        let key = fs::read(key).map_err(|e| read_error(key, e))?;
+        params = params.identify_with(Identity::from_pem(&cert, &key)?);
+    }
+    // A pinned certificate stands in for the usual chain and hostname checks
+    if account.cert_fingerprint.is_some() {
+        params = params
+            .dangerous_accept_invalid_certs(true)
+            .dangerous_accept_invalid_hostnames(true);
+    }
+    Ok(params.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+
+    /// Answer one SMTP session on `listener` without authentication, returning the commands and
+    /// the message data received.
+    fn fake_server(listener: TcpListener) -> thread::JoinHandle<(Vec<String>, String)> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reply = |line: &str| writer.write_all(format!("{}\r\n", line).as_bytes());
+            let mut reader = BufReader::new(stream);
+            let (mut commands, mut data) = (vec![], String::new());
+            reply("220 localhost ESMTP").unwrap();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let command = line.trim_end().to_owned();
+                line.clear();
+                let verb = command.split(' ').next().unwrap_or_default().to_uppercase();
+                commands.push(command);
+                match verb.as_str() {
+                    "EHLO" => reply("250 localhost"),
+                    "DATA" => {
+                        reply("354 go ahead").unwrap();
+                        while reader.read_line(&mut line).unwrap() > 0 && line != ".\r\n" {
+                            data.push_str(&line);
+                            line.clear();
+                        }
+                        line.clear();
+                        reply("250 queued")
+                    }
+                    "QUIT" => {
+                        reply("221 bye").unwrap();
+                        break;
+                    }
+                    _ => reply("250 ok"),
+                }
+                .unwrap();
+            }
+            (commands, data)
+        })
+    }
+
+    #[test]
+    fn sends_to_every_recipient() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = fake_server(listener);
+        let account = Account {
+            username: "me@example.com".to_owned(),
+            smtp: Some(Smtp {
+                server: "127.0.0.1".to_owned(),
+                port,
+                security: Security::None,
+                username: None,
+            }),
+            ..Account::default()
+        };
+        let message = Message::builder()
+            .from("Me <me@example.com>".parse().unwrap())
+            .to("alice@example.org".parse().unwrap())
+            .cc("bob@example.org".parse().unwrap())
+            .bcc("carol@example.org".parse().unwrap())
+            .subject("Lunch")
+            .body("Noon?".to_owned())
+            .unwrap();
+        send(&account, &message).unwrap();
+        let (commands, data) = server.join().unwrap();
+        assert!(commands.contains(&"MAIL FROM:<me@example.com>".to_owned()));
+        let rcpt: Vec<&str> = commands
+            .iter()
+            .filter_map(|c| c.strip_prefix("RCPT TO:"))
+            .collect();
+        assert_eq!(
+            rcpt,
+            [
+                "<alice@example.org>",
+                "<bob@example.org>",
+                "<carol@example.org>"
+            ]
+        );
+        assert!(!commands.iter().any(|c| c.starts_with("AUTH")));
+        assert_eq!(commands.last().map(String::as_str), Some("QUIT"));
+        assert!(data.contains("Subject: Lunch\r\n"), "{}", data);
+        assert!(data.contains("To: alice@example.org\r\n"), "{}", data);
+        assert!(!data.contains("carol"), "{}", data);
+        assert!(data.ends_with("\r\nNoon?\r\n"), "{}", data);
+    }
+
+    #[test]
+    fn needs_an_smtp_server() {
+        let message = Message::builder()
+            .from("me@example.com".parse().unwrap())
+            .to("alice@example.org".parse().unwrap())
+            .body("Hi".to_owned())
+            .unwrap();
+        assert!(matches!(
+            send(&Account::default(), &message),
+            Err(Error::Protocol(_))
+        ));
+    }
+}
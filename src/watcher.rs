@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeSet,
+    panic,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use imap::{extensions::idle::WaitOutcome, types::UnsolicitedResponse};
+
+use crate::{
+    config::Account,
+    connection,
+    email::{self, ImapSession},
+    error::{self, Error, Result},
+    widget::emails::EmailEntry,
+};
+
+/// How long a single IDLE lasts before checking whether another mailbox should be watched.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(20);
+/// How often to poll with NOOP when the server can't IDLE.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+pub enum WatchEvent {
+    /// New messages, newest first. `exists` is the size of the mailbox now.
+    Arrived {
+        inbox: String,
+        entries: Vec<EmailEntry>,
+        exists: usize,
+    },
+    /// Messages expunged, possibly by another client.
+    Vanished {
+        inbox: String,
+        uids: Vec<u32>,
+        exists: usize,
+    },
+    Error(Error),
+}
+
+struct Watch {
+    inbox: String,
+    uid_validity: u32,
+    /// Messages up to this UID are already listed, anything above has arrived since
+    newest_uid: u32,
+}
+
+/// Handle to a thread with its own IMAP connection that watches one mailbox for changes, so the
+/// worker's connection stays free for whatever the user does meanwhile.
+pub struct Watcher {
+    tx: Sender<Watch>,
+    rx: Receiver<WatchEvent>,
+}
+
+impl Watcher {
+    pub fn spawn(account: Account) -> Watcher {
+        let (watch_tx, watch_rx) = mpsc::channel::<Watch>();
+        let (evt_tx, evt_rx) = mpsc::channel::<WatchEvent>();
+        // Not joined on exit: it may be blocked in IDLE and notices the app is gone soon enough
+        thread::Builder::new()
+            .name("imap-watcher".to_owned())
+            .spawn(move || {
+                let tx = evt_tx.clone();
+                if let Err(payload) = panic::catch_unwind(|| run(account, watch_rx, evt_tx)) {
+                    let _ = tx.send(WatchEvent::Error(error::panicked(payload)));
+                }
+            })
+            .expect("failed to spawn imap watcher");
+        Watcher {
+            tx: watch_tx,
+            rx: evt_rx,
+        }
+    }
+
+    /// Start watching `inbox` in place of the previous mailbox.
+    pub fn watch(&self, inbox: String, uid_validity: u32, newest_uid: u32) {
+        let _ = self.tx.send(Watch {
+            inbox,
+            uid_validity,
+            newest_uid,
+        });
+    }
+
+    pub fn try_recv(&self) -> Option<WatchEvent> {
+        self.rx.try_recv().ok()
+    }
+}
+
+fn run(account: Account, rx: Receiver<Watch>, tx: Sender<WatchEvent>) {
+    let Ok(mut watch) = rx.recv() else {
+        return;
+    };
+    let mut session: Option<ImapSession> = None;
+    let mut backoff = None;
+    // UIDs of the watched mailbox, kept across reconnects to tell what happened while away
+    let mut known = BTreeSet::new();
+    loop {
+        let s = match session.as_mut() {
+            Some(s) => s,
+            // The worker reports the connection state, so failures here are only retried
+            None => match email::new_session(account.clone()) {
+                Ok(s) => {
+                    backoff = None;
+                    session.insert(s)
+                }
+                Err(_) => {
+                    let wait = connection::backoff(backoff);
+                    backoff = Some(wait);
+                    match rx.recv_timeout(wait) {
+                        Ok(next) => {
+                            watch = latest(next, &rx);
+                            known.clear();
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                    continue;
+                }
+            },
+        };
+        let next = match watch_mailbox(s, &watch, &mut known, &rx, &tx) {
+            Ok(next) => next,
+            // Lost the connection, so watch the same mailbox again once it's back
+            Err(Error::Network(_)) if s.noop().is_err() => {
+                session = None;
+                continue;
+            }
+            Err(e) => {
+                let _ = tx.send(WatchEvent::Error(e));
+                // Nothing to do until another mailbox is listed
+                rx.recv().ok()
+            }
+        };
+        match next {
+            Some(next) => {
+                watch = next;
+                known.clear();
+            }
+            None => break,
+        }
+    }
+    if let Some(mut s) = session {
+        let _ = s.logout();
+    }
+}
+
+/// Report changes to the mailbox of `watch` until another one should be watched, which is
+/// returned, or the app has quit.
+fn watch_mailbox(
+    s: &mut ImapSession,
+    watch: &Watch,
+    known: &mut BTreeSet<u32>,
+    rx: &Receiver<Watch>,
+    tx: &Sender<WatchEvent>,
+) -> Result<Option<Watch>> {
+    let idle = s.capabilities().map(|c| c.has_str("IDLE"))?;
+    email::select_uid_validity(s, watch.inbox.clone(), watch.uid_validity)?;
+    // Catches up on anything that arrived while the listing was being fetched
+    sync(s, watch, known, tx)?;
+    loop {
+        let changed = match idle {
+            true => {
+                let outcome = s.idle().and_then(|h| h.wait_with_timeout(IDLE_TIMEOUT))?;
+                matches!(outcome, WaitOutcome::MailboxChanged)
+            }
+            false => match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(next) => return Ok(Some(latest(next, rx))),
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+                Err(RecvTimeoutError::Timeout) => {
+                    s.noop()?;
+                    s.unsolicited_responses.try_iter().any(|r| {
+                        matches!(
+                            r,
+                            UnsolicitedResponse::Exists(_) | UnsolicitedResponse::Expunge(_)
+                        )
+                    })
+                }
+            },
+        };
+        match rx.try_recv() {
+            Ok(next) => return Ok(Some(latest(next, rx))),
+            Err(TryRecvError::Disconnected) => return Ok(None),
+            Err(TryRecvError::Empty) => {}
+        }
+        if changed {
+            sync(s, watch, known, tx)?;
+        }
+    }
+}
+
+/// Skip over mailboxes that were listed and left again in the meantime.
+fn latest(next: Watch, rx: &Receiver<Watch>) -> Watch {
+    rx.try_iter().last().unwrap_or(next)
+}
+
+/// Compare the UIDs in the mailbox with the `known` ones, reporting what arrived and vanished.
+fn sync(
+    s: &mut ImapSession,
+    watch: &Watch,
+    known: &mut BTreeSet<u32>,
+    tx: &Sender<WatchEvent>,
+) -> Result<()> {
+    let uids: BTreeSet<u32> = s.uid_search("ALL")?.into_iter().collect();
+    // Whatever the server said along the way is covered by the search
+    s.unsolicited_responses.try_iter().for_each(drop);
+    let exists = uids.len();
+    let mut arrived: Vec<u32> = uids
+        .difference(known)
+        .filter(|uid| **uid > watch.newest_uid)
+        .copied()
+        .collect();
+    arrived.reverse();
+    let vanished: Vec<u32> = known.difference(&uids).copied().collect();
+    *known = uids;
+    if !arrived.is_empty() {
+        let entries = email::uid_fetch_envelopes(s, &arrived, &watch.inbox, watch.uid_validity)?;
+        let _ = tx.send(WatchEvent::Arrived {
+            inbox: watch.inbox.to_owned(),
+            entries,
+            exists,
+        });
+    }
+    if !vanished.is_empty() {
+        let _ = tx.send(WatchEvent::Vanished {
+            inbox: watch.inbox.to_owned(),
+            uids: vanished,
+            exists,
+        });
+    }
+    Ok(())
+}
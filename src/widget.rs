@@ -1,18 +1,36 @@
-use std::slice::Iter;
+use std::cmp;
 
 use crossterm::event::Event;
 use ratatui::{
-    layout::Rect,
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Style, Stylize as _},
     widgets::{Block, Borders, ScrollbarState, TableState},
     Frame,
 };
 
-use crate::app::{Context, Mode};
+use crate::{
+    app::{Context, Mode},
+    email::Transfer,
+    keymap::{Action, Keymap, Scope},
+};
 
-use self::{emails::Emails, preview::Preview, search::Search, sidebar::Sidebar};
+use self::{
+    command::CommandLine,
+    emails::Emails,
+    help::Help,
+    notifications::Notifications,
+    picker::MailboxPicker,
+    preview::Preview,
+    search::{Search, SearchKind},
+    sidebar::Sidebar,
+};
 
+pub mod command;
 pub mod emails;
+pub mod help;
+pub mod input;
+pub mod notifications;
+pub mod picker;
 pub mod preview;
 pub mod search;
 pub mod sidebar;
@@ -23,6 +41,12 @@ pub enum Focusable {
     Sidebar,
     Emails,
     Preview,
+    Picker(Transfer),
+    /// Past errors and messages
+    Log,
+    Help,
+    /// The `:` prompt
+    Command,
 }
 
 #[derive(Default)]
@@ -31,59 +55,120 @@ pub struct Widgets {
     pub sidebar: Sidebar,
     pub email: Emails,
     pub preview: Preview,
+    pub picker: MailboxPicker,
+    pub notifications: Notifications,
+    pub help: Help,
+    pub command: CommandLine,
 }
 
 impl Widgets {
+    /// Input not bound to an action, for whatever has focus.
     pub fn on(&mut self, e: Event, ctx: &mut Context) {
         let new_mode = match ctx.mode.clone() {
-            Mode::Focus(f) => match f {
-                Focusable::Search => self.search.on(e),
-                Focusable::Emails => self.email.on(e),
-                Focusable::Sidebar => self.sidebar.on(e),
-                Focusable::Preview => self.preview.on(e),
-            },
+            Mode::Focus(f) => self.focused(&f).on(e),
             _ => None,
         };
+        self.after(new_mode, ctx);
+    }
+
+    /// Hand `action` to whatever has focus.
+    pub fn act(&mut self, action: Action, ctx: &mut Context) {
+        let new_mode = match ctx.mode.clone() {
+            Mode::Focus(f) => self.focused(&f).act(action),
+            Mode::Error(_) => self.notifications.act(action),
+            Mode::Loading(_) => None,
+        };
+        self.after(new_mode, ctx);
+    }
+
+    fn focused(&mut self, f: &Focusable) -> &mut dyn Widget {
+        match f {
+            Focusable::Search => &mut self.search,
+            Focusable::Sidebar => &mut self.sidebar,
+            Focusable::Emails => &mut self.email,
+            Focusable::Preview => &mut self.preview,
+            Focusable::Picker(_) => &mut self.picker,
+            Focusable::Log => &mut self.notifications,
+            Focusable::Help => &mut self.help,
+            Focusable::Command => &mut self.command,
+        }
+    }
+
+    /// Keep the filter and the message list in step, then switch to `new_mode` if any.
+    fn after(&mut self, new_mode: Option<Mode>, ctx: &mut Context) {
+        if self.search.kind == SearchKind::Filter {
+            match ctx.mode {
+                Mode::Focus(Focusable::Search) => self.email.set_filter(self.search.input.as_str()),
+                Mode::Focus(Focusable::Emails) if !self.email.is_filtered() => {
+                    self.search.input.clear()
+                }
+                _ => {}
+            }
+        }
         if let Some(m) = new_mode {
+            if let Mode::Focus(Focusable::Picker(op)) = m {
+                let account = self.email.targets().first().map(|e| e.account);
+                self.picker
+                    .open(op, self.sidebar.names(account.unwrap_or_default()));
+            }
             ctx.mode = m;
         }
     }
-}
 
-pub trait EnumIter<T> {
-    fn iter() -> Iter<'static, T>;
+    /// Name and keys of what `mode` has focused, if anything.
+    pub fn help(
+        &self,
+        mode: &Mode,
+        keymap: &Keymap,
+    ) -> Option<(&'static str, Vec<(String, &'static str)>)> {
+        let (name, actions) = match mode {
+            Mode::Focus(f) => match f {
+                Focusable::Search => ("Search", self.search.help()),
+                Focusable::Sidebar => ("Inboxes", self.sidebar.help()),
+                Focusable::Emails => ("Messages", self.email.help()),
+                Focusable::Preview => ("Preview", self.preview.help()),
+                Focusable::Picker(_) => ("Mailbox picker", self.picker.help()),
+                Focusable::Log => ("Message log", self.notifications.help()),
+                Focusable::Help => ("Keys", self.help.help()),
+                Focusable::Command => ("Command line", self.command.help()),
+            },
+            Mode::Error(_) => ("Error", self.notifications.help()),
+            Mode::Loading(_) => return None,
+        };
+        Some((name, keymap.describe(Scope::of(mode)?, &actions)))
+    }
 }
 
-// pub fn centered_rect(mut x_len: u16, mut y_len: u16, r: Rect) -> Rect {
-//     x_len = cmp::min(x_len, r.width);
-//     y_len = cmp::min(y_len, r.height);
-//     let popup_layout = Layout::new(
-//         Direction::Vertical,
-//         [
-//             Constraint::Length((r.height - y_len) / 2),
-//             Constraint::Length(y_len),
-//             Constraint::Length((r.height - y_len) / 2),
-//         ],
-//     )
-//     .split(r);
-//
-//     Layout::new(
-//         Direction::Horizontal,
-//         [
-//             Constraint::Length((r.width - x_len) / 2),
-//             Constraint::Length(x_len),
-//             Constraint::Length((r.width - x_len) / 2),
-//         ],
-//     )
-//     .split(popup_layout[1])[1]
-// }
+pub fn centered_rect(mut x_len: u16, mut y_len: u16, r: Rect) -> Rect {
+    x_len = cmp::min(x_len, r.width);
+    y_len = cmp::min(y_len, r.height);
+    let popup_layout = Layout::new(
+        Direction::Vertical,
+        [
+            Constraint::Length((r.height - y_len) / 2),
+            Constraint::Length(y_len),
+            Constraint::Length((r.height - y_len) / 2),
+        ],
+    )
+    .split(r);
+
+    Layout::new(
+        Direction::Horizontal,
+        [
+            Constraint::Length((r.width - x_len) / 2),
+            Constraint::Length(x_len),
+            Constraint::Length((r.width - x_len) / 2),
+        ],
+    )
+    .split(popup_layout[1])[1]
+}
 
 // pub fn clear(area: Rect, buf: &mut Buffer, fill: Color) {
 //     Clear.render(area, buf);
 //     Block::new().bg(fill).render(area, buf);
 // }
 
-fn focus_border(ctx: &Context, f: Focusable) -> Block {
+fn focus_border(ctx: &Context, f: Focusable) -> Block<'_> {
     Block::new()
         .borders(Borders::ALL)
         .border_style(match ctx.mode == Mode::Focus(f) {
@@ -94,8 +179,11 @@ fn focus_border(ctx: &Context, f: Focusable) -> Block {
 
 pub trait Widget {
     fn draw(&mut self, f: &mut Frame, area: Rect, ctx: &mut Context);
+    /// Events no key binding took, like the mouse or text being typed.
     fn on(&mut self, e: Event) -> Option<Mode>;
-    fn help(self) -> Option<(&'static str, &'static str)>;
+    fn act(&mut self, action: Action) -> Option<Mode>;
+    /// Actions to list in the help, in order.
+    fn help(&self) -> Vec<Action>;
 }
 
 pub struct StatefulTable<T> {
@@ -161,6 +249,27 @@ impl<T> StatefulTable<T> {
         self.scrollbar_state = self.scrollbar_state.position(len);
     }
 
+    pub fn extend<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        self.items.extend(items);
+        self.scrollbar_state = self.scrollbar_state.content_length(self.items.len());
+    }
+
+    /// Insert `items` before the current ones, keeping the same item selected.
+    pub fn prepend(&mut self, items: Vec<T>) {
+        let n = items.len();
+        self.items.splice(0..0, items);
+        self.scrollbar_state = self.scrollbar_state.content_length(self.items.len());
+        if let Some(i) = self.state.selected() {
+            self.select(i + n);
+        }
+    }
+
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        self.items.retain(f);
+        self.scrollbar_state = self.scrollbar_state.content_length(self.items.len());
+        self.next(0);
+    }
+
     pub fn select(&mut self, idx: usize) {
         self.state.select(Some(idx));
         self.scrollbar_state = self.scrollbar_state.position(idx);
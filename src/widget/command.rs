@@ -0,0 +1,152 @@
+use crossterm::event::{Event, KeyEvent, KeyEventKind};
+use ratatui::{layout::Rect, text::Line, widgets::Paragraph, Frame};
+
+use crate::{
+    app::{Context, LoadType, Mode},
+    command,
+    keymap::Action,
+};
+
+use super::{input::Input, Focusable, Widget};
+
+/// The `:` prompt, for running actions and commands by name.
+pub struct CommandLine {
+    pub input: Input,
+    /// Lines run before, oldest first
+    history: Vec<String>,
+    /// Position in `history` while going through it, `history.len()` being the line typed
+    browsing: usize,
+    /// The line typed before going through the history
+    typed: String,
+    /// Lines the one typed completes to, and which of them is shown
+    completions: Vec<String>,
+    completing: usize,
+    /// Completion candidates, taken when the prompt is opened
+    mailboxes: Vec<String>,
+    accounts: Vec<String>,
+    /// What had focus before, which commands apply to
+    pub return_to: Focusable,
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        CommandLine {
+            input: Input::default(),
+            history: vec![],
+            browsing: 0,
+            typed: String::new(),
+            completions: vec![],
+            completing: 0,
+            mailboxes: vec![],
+            accounts: vec![],
+            return_to: Focusable::Emails,
+        }
+    }
+}
+
+impl CommandLine {
+    pub fn open(&mut self, return_to: Focusable, mailboxes: Vec<String>, accounts: Vec<String>) {
+        self.input.clear();
+        self.browsing = self.history.len();
+        self.completions.clear();
+        self.mailboxes = mailboxes;
+        self.accounts = accounts;
+        self.return_to = return_to;
+    }
+
+    /// Show the next completion of what was typed, cycling through them.
+    fn complete(&mut self) {
+        if self.completions.is_empty() {
+            self.completions = command::complete(
+                self.input.as_str().trim_start(),
+                &self.mailboxes,
+                &self.accounts,
+            );
+            self.completing = 0;
+        } else {
+            self.completing = (self.completing + 1) % self.completions.len();
+        }
+        if let Some(line) = self.completions.get(self.completing) {
+            self.input.set(line);
+        }
+    }
+
+    /// Show the line `amt` steps away in the history.
+    fn browse(&mut self, amt: isize) {
+        if self.browsing == self.history.len() {
+            self.typed = self.input.as_str().to_owned();
+        }
+        let i = (self.browsing as isize + amt).clamp(0, self.history.len() as isize) as usize;
+        self.browsing = i;
+        self.input.set(self.history.get(i).unwrap_or(&self.typed));
+    }
+
+    /// Remember `line` to go back to, once even if run repeatedly.
+    fn remember(&mut self, line: &str) {
+        if self.history.last().is_none_or(|l| l != line) {
+            self.history.push(line.to_owned());
+        }
+    }
+}
+
+impl Widget for CommandLine {
+    fn draw(&mut self, f: &mut Frame, area: Rect, _ctx: &mut Context) {
+        f.render_widget(
+            Paragraph::new(Line::from(format!(":{}", self.input.as_str()))),
+            area,
+        );
+        f.set_cursor(area.x + 1 + self.input.cursor_width(), area.y);
+    }
+
+    fn on(&mut self, e: Event) -> Option<Mode> {
+        if let Event::Key(
+            key @ KeyEvent {
+                kind: KeyEventKind::Press,
+                ..
+            },
+        ) = e
+        {
+            if self.input.on(&key) {
+                self.completions.clear();
+            }
+        }
+        None
+    }
+
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        if self.input.act(action) {
+            self.completions.clear();
+            return None;
+        }
+        match action {
+            Action::Complete => self.complete(),
+            Action::Up => self.browse(-1),
+            Action::Down => self.browse(1),
+            Action::Submit => {
+                let line = self.input.as_str().trim().to_owned();
+                if line.is_empty() {
+                    return Some(Mode::Focus(self.return_to.clone()));
+                }
+                self.remember(&line);
+                return Some(Mode::Loading(LoadType::Command(line)));
+            }
+            Action::Close => return Some(Mode::Focus(self.return_to.clone())),
+            _ => {}
+        }
+        None
+    }
+
+    fn help(&self) -> Vec<Action> {
+        vec![
+            Action::Submit,
+            Action::Close,
+            Action::Complete,
+            Action::Up,
+            Action::Down,
+            Action::LineStart,
+            Action::LineEnd,
+            Action::DeleteWord,
+            Action::ClearLine,
+        ]
+    }
+}
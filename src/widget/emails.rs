@@ -1,28 +1,159 @@
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, MouseEvent, MouseEventKind};
+use std::cmp::Ordering;
+
+use chrono::{DateTime, FixedOffset, Local};
+use crossterm::event::{Event, MouseEvent, MouseEventKind};
 use ratatui::{
-    layout::{Constraint, Margin, Position, Rect},
+    layout::{Constraint, Position, Rect},
     style::{Color, Style, Stylize},
     symbols::line,
+    text::{Line, Span},
     widgets::{
         Block, Borders, Cell, Clear, Paragraph, Row, Scrollbar, ScrollbarOrientation, Table,
     },
     Frame,
 };
+use serde::{Deserialize, Serialize};
 use unicode_width::UnicodeWidthChar as _;
 
-use crate::app::{Context, LoadType, Mode};
+use crate::{
+    app::{Context, LoadType, Mode},
+    compose::ReplyKind,
+    config::DateFormat,
+    email::{MailFlag, Transfer},
+    fuzzy::fuzzy_match,
+    keymap::Action,
+};
 
 use super::{Focusable, StatefulTable, Widget};
 
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Flags {
+    pub seen: bool,
+    pub flagged: bool,
+    pub answered: bool,
+    pub deleted: bool,
+}
+
+impl Flags {
+    pub fn get(&self, flag: MailFlag) -> bool {
+        match flag {
+            MailFlag::Seen => self.seen,
+            MailFlag::Flagged => self.flagged,
+            MailFlag::Deleted => self.deleted,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct EmailEntry {
+    /// Index into the configured accounts
+    pub account: usize,
+    pub inbox: String,
+    pub uid: u32,
+    /// UIDVALIDITY of the mailbox at the time `uid` was fetched
+    pub uid_validity: u32,
     pub from: String,
     pub subject: String,
-    pub date: String,
+    pub date: Option<DateTime<FixedOffset>>,
+    pub flags: Flags,
+    pub attachment: bool,
+    /// The headers couldn't be parsed, so `subject` is a placeholder
+    pub unreadable: bool,
+    /// Picked by the user as a target of the next action
+    pub marked: bool,
+}
+
+impl EmailEntry {
+    fn indicators(&self) -> Line<'static> {
+        let status = match self.flags {
+            Flags { deleted: true, .. } => Span::raw("\u{f1f8}").fg(Color::Red),
+            Flags { seen: false, .. } => Span::raw("\u{f0e0}").fg(Color::LightBlue),
+            Flags { answered: true, .. } => Span::raw("\u{f112}").fg(Color::Green),
+            _ => Span::raw(" "),
+        };
+        let flagged = match self.flags.flagged {
+            true => Span::raw("\u{f005}").fg(Color::Yellow),
+            false => Span::raw(" "),
+        };
+        let attachment = match self.attachment {
+            true => Span::raw("\u{f0c6}").fg(Color::Gray),
+            false => Span::raw(" "),
+        };
+        Line::from(vec![status, flagged, attachment])
+    }
+}
+
+/// What the message list is showing, of which only a page or so may be loaded.
+#[derive(Clone, Default)]
+pub struct Listing {
+    pub account: usize,
+    pub inbox: String,
+    pub uid_validity: u32,
+    /// Number of messages in the listing, loaded or not
+    pub total: usize,
+    /// Search criteria, `None` when showing the whole mailbox
+    pub criteria: Option<String>,
+}
+
+/// Order of the message list.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sort {
+    /// As the server lists them, newest arrival first
+    #[default]
+    Arrival,
+    /// Newest first, by the Date header
+    Date,
+    From,
+    Subject,
+    /// Unread first, then in arrival order
+    Unread,
+}
+
+const SORTS: [(Sort, &str); 5] = [
+    (Sort::Arrival, "arrival"),
+    (Sort::Date, "date"),
+    (Sort::From, "from"),
+    (Sort::Subject, "subject"),
+    (Sort::Unread, "unread"),
+];
+
+impl Sort {
+    pub fn parse(name: &str) -> Option<Sort> {
+        SORTS.iter().find(|s| s.1 == name).map(|s| s.0)
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        SORTS.iter().map(|s| s.1)
+    }
+
+    fn apply(&self, entries: &mut [EmailEntry]) {
+        if *self != Sort::Arrival {
+            entries.sort_by(|a, b| self.compare(a, b));
+        }
+    }
+
+    fn compare(&self, a: &EmailEntry, b: &EmailEntry) -> Ordering {
+        match self {
+            Sort::Arrival => Ordering::Equal,
+            Sort::Date => b.date.cmp(&a.date),
+            Sort::From => a.from.to_lowercase().cmp(&b.from.to_lowercase()),
+            Sort::Subject => a.subject.to_lowercase().cmp(&b.subject.to_lowercase()),
+            Sort::Unread => a.flags.seen.cmp(&b.flags.seen),
+        }
+    }
 }
 
 pub struct Emails {
     pub table: StatefulTable<EmailEntry>,
     last_area: Rect,
+    sort: Sort,
+    filter: String,
+    /// The full list while `filter` hides part of it
+    unfiltered: Option<Vec<EmailEntry>>,
+    /// One per account when showing several accounts' mailboxes together
+    listings: Vec<Listing>,
+    /// Set once fetching a page failed, so the list isn't paged again until reloaded
+    paging_stopped: bool,
 }
 
 impl Default for Emails {
@@ -30,42 +161,456 @@ impl Default for Emails {
         Emails {
             table: StatefulTable::new(),
             last_area: Rect::default(),
+            sort: Sort::default(),
+            filter: String::new(),
+            unfiltered: None,
+            listings: vec![],
+            paging_stopped: false,
         }
     }
 }
 
+/// How close to the end of the loaded list the selection may get before the next page is fetched.
+const FETCH_AHEAD: usize = 10;
+
+/// Char indices of `from` and `subject` matched by `filter`. Every whitespace separated term of
+/// the filter has to match one of the two.
+fn filter_matches(filter: &str, e: &EmailEntry) -> Option<(Vec<usize>, Vec<usize>)> {
+    let (mut from, mut subject) = (vec![], vec![]);
+    for term in filter.split_whitespace() {
+        if let Some(m) = fuzzy_match(term, &e.from) {
+            from.extend(m);
+        } else if let Some(m) = fuzzy_match(term, &e.subject) {
+            subject.extend(m);
+        } else {
+            return None;
+        }
+    }
+    Some((from, subject))
+}
+
+/// `text` without zero width chars, with the chars at `matches` highlighted.
+fn highlight(text: &str, matches: &[usize]) -> Line<'static> {
+    let mut spans: Vec<Span> = vec![];
+    let mut run = String::new();
+    let mut run_matched = false;
+    for (i, c) in text.chars().enumerate() {
+        if c.width().is_none_or(|w| w == 0) {
+            continue;
+        }
+        let matched = matches.contains(&i);
+        if matched != run_matched && !run.is_empty() {
+            spans.push(highlight_span(std::mem::take(&mut run), run_matched));
+        }
+        run_matched = matched;
+        run.push(c);
+    }
+    spans.push(highlight_span(run, run_matched));
+    Line::from(spans)
+}
+
+fn highlight_span(s: String, matched: bool) -> Span<'static> {
+    match matched {
+        true => Span::raw(s).fg(Color::Yellow).bold().not_italic(),
+        false => Span::raw(s),
+    }
+}
+
+/// Render `date` in local time: today's messages show the time, the past week's the weekday and
+/// anything older the full date.
+fn format_date(date: DateTime<FixedOffset>, now: DateTime<Local>, fmt: &DateFormat) -> String {
+    let date = date.with_timezone(&Local);
+    let days = now
+        .date_naive()
+        .signed_duration_since(date.date_naive())
+        .num_days();
+    let fmt = match days {
+        _ if !fmt.relative => &fmt.older,
+        0 => &fmt.today,
+        1..=6 => &fmt.week,
+        _ => &fmt.older,
+    };
+    date.format(fmt).to_string()
+}
+
 impl Emails {
-    pub fn set_entries(&mut self, entries: Vec<EmailEntry>) {
+    pub fn set_entries(&mut self, mut entries: Vec<EmailEntry>, listing: Listing) {
+        self.unfiltered = None;
+        self.listings = vec![listing];
+        self.paging_stopped = false;
+        self.sort.apply(&mut entries);
         self.table.with_items(entries);
+        let filter = std::mem::take(&mut self.filter);
+        self.set_filter(&filter);
+    }
+
+    /// Forget what the list shows, before [`merge`](Emails::merge)ing in a new set of listings.
+    /// The entries stay until replaced.
+    pub fn clear_listings(&mut self) {
+        self.listings.clear();
+        self.paging_stopped = false;
+    }
+
+    /// Show `entries` among those of other accounts, newest first, in place of any shown for the
+    /// same account before.
+    pub fn merge(&mut self, entries: Vec<EmailEntry>, listing: Listing) {
+        let account = listing.account;
+        self.listings.retain(|l| l.account != account);
+        self.listings.push(listing);
+        let selected = self
+            .selected()
+            .map(|e| (e.account, e.inbox.to_owned(), e.uid));
+        let mut all = self
+            .unfiltered
+            .take()
+            .unwrap_or_else(|| std::mem::take(&mut self.table.items));
+        let listings = &self.listings;
+        all.retain(|e| e.account != account && listings.iter().any(|l| l.account == e.account));
+        all.extend(entries);
+        all.sort_by_key(|e| std::cmp::Reverse(e.date));
+        self.sort.apply(&mut all);
+        self.table.with_items(all);
+        let filter = std::mem::take(&mut self.filter);
+        self.set_filter(&filter);
+        if let Some((account, inbox, uid)) = selected {
+            self.select(account, &inbox, uid);
+        }
+    }
+
+    /// Add the next page of the listing. Anything already loaded, or from another mailbox than
+    /// the listing's, is skipped. An empty page means the server has nothing older after all.
+    pub fn append(&mut self, entries: Vec<EmailEntry>) {
+        if entries.is_empty() {
+            let loaded = self.loaded();
+            if let [listing] = self.listings.as_mut_slice() {
+                listing.total = loaded;
+            }
+            return;
+        }
+        let entries = self.unseen(entries);
+        if let Some(all) = &mut self.unfiltered {
+            all.extend(entries.iter().cloned());
+        }
+        let filter = &self.filter;
+        self.table.extend(
+            entries
+                .into_iter()
+                .filter(|e| filter.is_empty() || filter_matches(filter, e).is_some()),
+        );
+        self.resort();
+    }
+
+    /// Put messages that just arrived in `inbox` at the top, if the list shows that whole
+    /// mailbox. `exists` is the mailbox's new size.
+    pub fn insert(&mut self, account: usize, inbox: &str, entries: Vec<EmailEntry>, exists: usize) {
+        let Some(listing) = self.listing_mut(account, inbox) else {
+            return;
+        };
+        listing.total = exists;
+        let mut entries = self.unseen(entries);
+        entries.sort_unstable_by_key(|e| std::cmp::Reverse(e.uid));
+        if let Some(all) = &mut self.unfiltered {
+            all.splice(0..0, entries.iter().cloned());
+        }
+        let filter = &self.filter;
+        self.table.prepend(
+            entries
+                .into_iter()
+                .filter(|e| filter.is_empty() || filter_matches(filter, e).is_some())
+                .collect(),
+        );
+        self.resort();
+    }
+
+    pub fn set_sort(&mut self, sort: Sort) {
+        self.sort = sort;
+        // Back to how the entries came in, which combined listings sort by date
+        let combined = self.listings.len() > 1;
+        let arrival = |a: &EmailEntry, b: &EmailEntry| match combined {
+            false => b.uid.cmp(&a.uid),
+            true => b.date.cmp(&a.date),
+        };
+        match sort {
+            Sort::Arrival => self.sort_by(arrival),
+            _ => self.resort(),
+        }
+    }
+
+    /// Put what was added back in order, keeping the same entry selected. Arrival order is how
+    /// entries are added in the first place.
+    fn resort(&mut self) {
+        let sort = self.sort;
+        if sort != Sort::Arrival {
+            self.sort_by(|a, b| sort.compare(a, b));
+        }
+    }
+
+    fn sort_by<F: Fn(&EmailEntry, &EmailEntry) -> Ordering>(&mut self, compare: F) {
+        let selected = self
+            .selected()
+            .map(|e| (e.account, e.inbox.to_owned(), e.uid));
+        self.table.items.sort_by(&compare);
+        if let Some(all) = &mut self.unfiltered {
+            all.sort_by(&compare);
+        }
+        if let Some((account, inbox, uid)) = selected {
+            self.select(account, &inbox, uid);
+        }
+    }
+
+    /// Drop messages another client expunged from `inbox`, which now holds `exists` messages.
+    pub fn vanished(&mut self, account: usize, inbox: &str, uids: &[u32], exists: usize) {
+        self.remove(account, inbox, uids);
+        if let Some(listing) = self.listing_mut(account, inbox) {
+            listing.total = exists;
+        }
+    }
+
+    /// The listing of the whole of `inbox`, if the list shows it.
+    fn listing_mut(&mut self, account: usize, inbox: &str) -> Option<&mut Listing> {
+        self.listings
+            .iter_mut()
+            .find(|l| l.account == account && l.inbox == inbox && l.criteria.is_none())
+    }
+
+    /// `entries` of the listed mailboxes that aren't loaded yet.
+    fn unseen(&self, entries: Vec<EmailEntry>) -> Vec<EmailEntry> {
+        let all = self.unfiltered.as_ref().unwrap_or(&self.table.items);
+        entries
+            .into_iter()
+            .filter(|e| {
+                self.listings
+                    .iter()
+                    .any(|l| l.account == e.account && l.inbox == e.inbox)
+            })
+            .filter(|e| !all.iter().any(|a| a.account == e.account && a.uid == e.uid))
+            .collect()
+    }
+
+    /// Stop asking for more pages after fetching one failed. The total stays that of the
+    /// mailbox, which still has the messages that couldn't be loaded.
+    pub fn stop_paging(&mut self) {
+        self.paging_stopped = true;
+    }
+
+    fn loaded(&self) -> usize {
+        self.unfiltered
+            .as_ref()
+            .map(|a| a.len())
+            .unwrap_or(self.table.items.len())
+    }
+
+    fn total(&self) -> usize {
+        self.listings.iter().map(|l| l.total).sum()
+    }
+
+    /// The listing to fetch the next page of and the lowest UID loaded so far, once the selection
+    /// is near the end of an incomplete, unfiltered list. Combined listings aren't paged.
+    pub fn next_page(&self) -> Option<(&Listing, u32)> {
+        let [listing] = self.listings.as_slice() else {
+            return None;
+        };
+        if self.paging_stopped || self.is_filtered() || self.loaded() >= listing.total {
+            return None;
+        }
+        let selected = self.table.state.selected()?;
+        if selected + FETCH_AHEAD < self.table.items.len() {
+            return None;
+        }
+        Some((listing, self.table.items.iter().map(|e| e.uid).min()?))
+    }
+
+    /// Narrow the list to entries fuzzy matching `filter` on sender or subject. An empty filter
+    /// restores the full list.
+    pub fn set_filter(&mut self, filter: &str) {
+        let filter = filter.trim();
+        if filter == self.filter {
+            return;
+        }
+        let selected = self
+            .selected()
+            .map(|e| (e.account, e.inbox.to_owned(), e.uid));
+        let all = self
+            .unfiltered
+            .take()
+            .unwrap_or_else(|| std::mem::take(&mut self.table.items));
+        self.filter = filter.to_owned();
+        if filter.is_empty() {
+            self.table.with_items(all);
+        } else {
+            let matches = all
+                .iter()
+                .filter(|e| filter_matches(filter, e).is_some())
+                .cloned()
+                .collect();
+            self.unfiltered = Some(all);
+            self.table.with_items(matches);
+        }
+        if let Some((account, inbox, uid)) = selected {
+            self.select(account, &inbox, uid);
+        }
+    }
+
+    fn select(&mut self, account: usize, inbox: &str, uid: u32) {
+        let i = self
+            .table
+            .items
+            .iter()
+            .position(|e| e.account == account && e.inbox == inbox && e.uid == uid);
+        if let Some(i) = i {
+            self.table.select(i);
+        }
+    }
+
+    pub fn is_filtered(&self) -> bool {
+        !self.filter.is_empty()
+    }
+
+    pub fn selected(&self) -> Option<&EmailEntry> {
+        self.table
+            .state
+            .selected()
+            .and_then(|i| self.table.items.get(i))
+    }
+
+    /// Apply `f` to the entry for `uid`, in the full list as well as the filtered one.
+    pub fn update<F: FnMut(&mut EmailEntry)>(
+        &mut self,
+        account: usize,
+        inbox: &str,
+        uid: u32,
+        mut f: F,
+    ) {
+        let entries = self
+            .table
+            .items
+            .iter_mut()
+            .chain(self.unfiltered.iter_mut().flatten())
+            .filter(|e| e.account == account && e.uid == uid && e.inbox == inbox);
+        for entry in entries {
+            f(entry);
+        }
+    }
+
+    /// The entries an action applies to: every marked entry, or the selected one if none are.
+    pub fn targets(&self) -> Vec<&EmailEntry> {
+        let marked: Vec<&EmailEntry> = self.table.items.iter().filter(|e| e.marked).collect();
+        match marked.is_empty() {
+            true => self.selected().into_iter().collect(),
+            false => marked,
+        }
+    }
+
+    pub fn unmark_all(&mut self) {
+        self.table
+            .items
+            .iter_mut()
+            .chain(self.unfiltered.iter_mut().flatten())
+            .for_each(|e| e.marked = false);
+    }
+
+    pub fn remove(&mut self, account: usize, inbox: &str, uids: &[u32]) {
+        self.retain(|e| !(e.account == account && e.inbox == inbox && uids.contains(&e.uid)));
+    }
+
+    /// Drop the entries of `inbox` that an expunge removed from the server.
+    pub fn remove_deleted(&mut self, account: usize, inbox: &str) {
+        self.retain(|e| !(e.flags.deleted && e.account == account && e.inbox == inbox));
+    }
+
+    fn retain<F: Fn(&EmailEntry) -> bool>(&mut self, f: F) {
+        let all = self.unfiltered.as_ref().unwrap_or(&self.table.items);
+        for listing in &mut self.listings {
+            let removed = all
+                .iter()
+                .filter(|e| e.account == listing.account && e.inbox == listing.inbox && !f(e))
+                .count();
+            listing.total = listing.total.saturating_sub(removed);
+        }
+        self.table.retain(&f);
+        if let Some(all) = &mut self.unfiltered {
+            all.retain(&f);
+        }
+    }
+
+    /// The account shown, for the title.
+    fn name(&self, ctx: &Context) -> String {
+        match self.listings.as_slice() {
+            [listing] => ctx
+                .accounts
+                .get(listing.account)
+                .map(|a| a.name.to_owned())
+                .unwrap_or_default(),
+            [] => String::new(),
+            _ => "All inboxes".to_owned(),
+        }
+    }
+
+    fn toggle(&self, flag: MailFlag) -> Option<Mode> {
+        self.selected()
+            .map(|e| Mode::Loading(LoadType::SetFlag(flag, !e.flags.get(flag))))
     }
 }
 
 impl Widget for Emails {
     fn draw(&mut self, f: &mut Frame, area: Rect, ctx: &mut Context) {
-        let title = match ctx.mode == Mode::Loading(LoadType::FetchEmails) {
-            true => "Loading...".to_owned(),
-            false => format!("Emails: {}", ctx.config.username),
-        };
+        let title = ctx
+            .loading_title(&LoadType::FetchEmails)
+            .or_else(|| ctx.loading_title(&LoadType::Search))
+            .or_else(|| ctx.loading_title(&LoadType::FetchMore))
+            .unwrap_or_else(|| match self.filter.is_empty() {
+                true if self.loaded() < self.total() => format!(
+                    "Emails: {} ({} of {})",
+                    self.name(ctx),
+                    self.loaded(),
+                    self.total()
+                ),
+                true => format!("Emails: {}", self.name(ctx)),
+                false => format!(
+                    "Emails: {} ({}/{} matching \"{}\")",
+                    self.name(ctx),
+                    self.table.items.len(),
+                    self.unfiltered
+                        .as_ref()
+                        .map(|a| a.len())
+                        .unwrap_or_default(),
+                    self.filter
+                ),
+            });
         // let rows: Vec<Row> = vec![];
         let rows = self.table.items.iter().map(|i| {
-            Row::new([
-                // Cell::new("".to_owned()).fg(Color::Yellow),
-                Cell::new("".to_owned()).fg(Color::Red),
-                Cell::new(i.from.to_owned()),
+            let (from_matches, subject_matches) = match self.filter.is_empty() {
+                true => (vec![], vec![]),
+                false => filter_matches(&self.filter, i).unwrap_or_default(),
+            };
+            let row = Row::new([
+                Cell::new(i.indicators()),
+                Cell::new(highlight(&i.from, &from_matches)),
+                Cell::new(highlight(&i.subject, &subject_matches))
+                    .italic()
+                    .fg(match i.unreadable {
+                        true => Color::DarkGray,
+                        false => Color::Gray,
+                    }),
                 Cell::new(
-                    i.subject
-                        .chars()
-                        .filter(|x| x.width().is_some_and(|x| x != 0)) // Filter 0 width chars
-                        .collect::<String>(),
-                )
-                .italic()
-                .fg(Color::Gray),
-                Cell::new(i.date.to_owned()),
+                    i.date
+                        .map(|d| format_date(d, Local::now(), &ctx.config.date_format))
+                        .unwrap_or_default(),
+                ),
             ])
-            .white()
+            .white();
+            let row = match i.marked {
+                true => row.on_blue(),
+                false => row,
+            };
+            match i.flags {
+                Flags { deleted: true, .. } => row.crossed_out().fg(Color::DarkGray),
+                Flags { seen: false, .. } => row.bold(),
+                _ => row,
+            }
         });
         let widths = [
-            Constraint::Length(1),
+            Constraint::Length(3),
             Constraint::Max(15),
             Constraint::Min(25),
             Constraint::Max(12),
@@ -132,53 +677,24 @@ impl Widget for Emails {
     }
 
     fn on(&mut self, e: Event) -> Option<Mode> {
-        if let Event::Key(KeyEvent {
-            code,
-            kind: KeyEventKind::Press,
-            ..
-        }) = e
-        {
-            match code {
-                KeyCode::Char('k') => {
-                    self.table.next(-1);
-                }
-                KeyCode::Char('j') => {
-                    self.table.next(1);
-                }
-                KeyCode::Char('g') => {
-                    self.table.first();
-                }
-                KeyCode::Char('G') => {
-                    self.table.last();
-                }
-                KeyCode::Enter => {
-                    return Some(Mode::Loading(LoadType::FetchPreview));
-                }
-                KeyCode::Char(' ') => {
-                    return Some(Mode::Loading(LoadType::FetchPreview));
-                }
-                _ => {}
-            }
-        } else if let Event::Mouse(MouseEvent {
+        if let Event::Mouse(MouseEvent {
             kind, row, column, ..
         }) = e
         {
             match kind {
-                MouseEventKind::ScrollUp => {
-                    if self.last_area.contains(Position::new(column, row)) {
-                        match self.table.state.offset() {
-                            2.. => *self.table.state.offset_mut() -= 2,
-                            1 => *self.table.state.offset_mut() = 0,
-                            _ => {}
-                        }
-                        self.table.next(-2);
+                MouseEventKind::ScrollUp if self.last_area.contains(Position::new(column, row)) => {
+                    match self.table.state.offset() {
+                        2.. => *self.table.state.offset_mut() -= 2,
+                        1 => *self.table.state.offset_mut() = 0,
+                        _ => {}
                     }
+                    self.table.next(-2);
                 }
-                MouseEventKind::ScrollDown => {
-                    if self.last_area.contains(Position::new(column, row)) {
-                        *self.table.state.offset_mut() += 2;
-                        self.table.next(2);
-                    }
+                MouseEventKind::ScrollDown
+                    if self.last_area.contains(Position::new(column, row)) =>
+                {
+                    *self.table.state.offset_mut() += 2;
+                    self.table.next(2);
                 }
                 _ => {}
             }
@@ -186,7 +702,128 @@ impl Widget for Emails {
         None
     }
 
-    fn help(self) -> Option<(&'static str, &'static str)> {
-        todo!()
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        let reply = |kind| Some(Mode::Loading(LoadType::Reply(kind, Focusable::Emails)));
+        match action {
+            Action::Up => self.table.next(-1),
+            Action::Down => self.table.next(1),
+            Action::First => self.table.first(),
+            Action::Last => self.table.last(),
+            Action::Select => return Some(Mode::Loading(LoadType::FetchPreview)),
+            Action::ToggleRead => return self.toggle(MailFlag::Seen),
+            Action::ToggleFlagged => return self.toggle(MailFlag::Flagged),
+            Action::Delete => {
+                return Some(Mode::Loading(LoadType::SetFlag(MailFlag::Deleted, true)));
+            }
+            Action::Undelete => {
+                return Some(Mode::Loading(LoadType::SetFlag(MailFlag::Deleted, false)));
+            }
+            Action::Expunge => return Some(Mode::Loading(LoadType::Expunge)),
+            Action::Mark => {
+                if let Some(e) = self.selected() {
+                    let (account, inbox, uid, marked) =
+                        (e.account, e.inbox.to_owned(), e.uid, e.marked);
+                    self.update(account, &inbox, uid, |e| e.marked = !marked);
+                    self.table.next(1);
+                }
+            }
+            Action::Cancel => self.set_filter(""),
+            Action::Move => return Some(Mode::Focus(Focusable::Picker(Transfer::Move))),
+            Action::Copy => return Some(Mode::Focus(Focusable::Picker(Transfer::Copy))),
+            Action::Archive => return Some(Mode::Loading(LoadType::Archive)),
+            Action::Trash => return Some(Mode::Loading(LoadType::Trash)),
+            Action::Reply => return reply(ReplyKind::Reply),
+            Action::ReplyAll => return reply(ReplyKind::ReplyAll),
+            Action::Forward => return reply(ReplyKind::Forward),
+            Action::ForwardAttached => return reply(ReplyKind::ForwardAttached),
+            _ => {}
+        }
+        None
+    }
+
+    fn help(&self) -> Vec<Action> {
+        vec![
+            Action::Down,
+            Action::Up,
+            Action::First,
+            Action::Last,
+            Action::Select,
+            Action::Mark,
+            Action::ToggleRead,
+            Action::ToggleFlagged,
+            Action::Delete,
+            Action::Undelete,
+            Action::Expunge,
+            Action::Archive,
+            Action::Trash,
+            Action::Move,
+            Action::Copy,
+            Action::Reply,
+            Action::ReplyAll,
+            Action::Forward,
+            Action::ForwardAttached,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn formats(relative: bool) -> DateFormat {
+        DateFormat {
+            today: "today %H:%M".to_owned(),
+            week: "week %a".to_owned(),
+            older: "%Y-%m-%d".to_owned(),
+            relative,
+        }
+    }
+
+    fn local(y: i32, m: u32, d: u32, h: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, m, d, h, 30, 0).unwrap()
+    }
+
+    fn format(date: DateTime<Local>, relative: bool) -> String {
+        // Friday
+        let now = local(2024, 3, 15, 14);
+        format_date(date.fixed_offset(), now, &formats(relative))
+    }
+
+    #[test]
+    fn today_shows_the_time() {
+        assert_eq!(format(local(2024, 3, 15, 9), true), "today 09:30");
+    }
+
+    #[test]
+    fn past_week_shows_the_weekday() {
+        assert_eq!(format(local(2024, 3, 14, 23), true), "week Thu");
+        assert_eq!(format(local(2024, 3, 9, 12), true), "week Sat");
+    }
+
+    #[test]
+    fn older_shows_the_date() {
+        assert_eq!(format(local(2024, 3, 8, 12), true), "2024-03-08");
+        assert_eq!(format(local(2019, 12, 31, 12), true), "2019-12-31");
+    }
+
+    #[test]
+    fn future_dates_show_the_date() {
+        assert_eq!(format(local(2024, 3, 16, 12), true), "2024-03-16");
+    }
+
+    #[test]
+    fn without_relative_everything_shows_the_date() {
+        assert_eq!(format(local(2024, 3, 15, 9), false), "2024-03-15");
+        assert_eq!(format(local(2024, 3, 14, 9), false), "2024-03-14");
+    }
+
+    #[test]
+    fn converts_to_local_time() {
+        let date = local(2024, 3, 15, 9);
+        let elsewhere = date.with_timezone(&FixedOffset::east_opt(-11 * 3600).unwrap());
+        let now = local(2024, 3, 15, 14);
+        assert_eq!(format_date(elsewhere, now, &formats(true)), "today 09:30");
     }
 }
@@ -0,0 +1,112 @@
+use crossterm::event::Event;
+use ratatui::{
+    layout::Rect,
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, BorderType, Borders, Clear, Paragraph},
+    Frame,
+};
+
+use crate::{
+    app::{Context, Mode},
+    keymap::Action,
+};
+
+use super::{centered_rect, Focusable, Widget};
+
+/// Width of the key column.
+const KEY_WIDTH: usize = 14;
+
+/// Keys that work everywhere and those of the widget that was focused, over everything else.
+pub struct Help {
+    /// Titled groups of keys and what they do
+    sections: Vec<(&'static str, Vec<(String, &'static str)>)>,
+    scroll: u16,
+    lines: u16,
+    return_to: Focusable,
+}
+
+impl Default for Help {
+    fn default() -> Self {
+        Help {
+            sections: vec![],
+            scroll: 0,
+            lines: 0,
+            return_to: Focusable::Emails,
+        }
+    }
+}
+
+impl Help {
+    pub fn open(
+        &mut self,
+        return_to: Focusable,
+        sections: Vec<(&'static str, Vec<(String, &'static str)>)>,
+    ) {
+        // A title and a blank line between sections
+        let lines: u16 = sections.iter().map(|(_, keys)| keys.len() as u16 + 2).sum();
+        self.lines = lines.saturating_sub(1);
+        self.sections = sections;
+        self.scroll = 0;
+        self.return_to = return_to;
+    }
+}
+
+/// `keys` as a single line of key and description pairs, as much of it as fits.
+pub fn hint_line(keys: &[(String, &'static str)]) -> Line<'static> {
+    let spans = keys.iter().flat_map(|(key, what)| {
+        [
+            Span::from(format!(" {} ", key)).bold().light_cyan(),
+            Span::from(format!("{} ", what)),
+        ]
+    });
+    Line::from(spans.collect::<Vec<_>>())
+}
+
+impl Widget for Help {
+    fn draw(&mut self, f: &mut Frame, area: Rect, _ctx: &mut Context) {
+        let area = centered_rect(56, self.lines + 2, area);
+        let mut lines = vec![];
+        for (title, keys) in &self.sections {
+            lines.push(Line::from(Span::from(*title).bold()));
+            lines.extend(keys.iter().map(|(key, what)| {
+                Line::from(vec![
+                    Span::from(format!("  {:<1$}", key, KEY_WIDTH)).light_cyan(),
+                    Span::from(*what),
+                ])
+            }));
+            lines.push(Line::default());
+        }
+        lines.pop();
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(lines).scroll((self.scroll, 0)).block(
+                Block::new()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::new().light_cyan())
+                    .title("Keys")
+                    .title_bottom(Line::from("Esc to close").right_aligned()),
+            ),
+            area,
+        );
+    }
+
+    fn on(&mut self, _e: Event) -> Option<Mode> {
+        None
+    }
+
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        match action {
+            Action::Up => self.scroll = self.scroll.saturating_sub(1),
+            Action::Down => self.scroll = (self.scroll + 1).min(self.lines.saturating_sub(1)),
+            Action::Close => return Some(Mode::Focus(self.return_to.clone())),
+            _ => {}
+        }
+        None
+    }
+
+    fn help(&self) -> Vec<Action> {
+        vec![Action::Down, Action::Up, Action::Close]
+    }
+}
@@ -0,0 +1,111 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_width::UnicodeWidthStr as _;
+
+use crate::keymap::Action;
+
+/// A line of text being edited, for the search box and the command line.
+#[derive(Default)]
+pub struct Input {
+    text: String,
+    /// Cursor position, in chars
+    cursor: usize,
+}
+
+impl Input {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Replace the text, with the cursor at its end.
+    pub fn set(&mut self, text: &str) {
+        self.text = text.to_owned();
+        self.end();
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.chars().count();
+    }
+
+    /// Columns taken by the text before the cursor.
+    pub fn cursor_width(&self) -> u16 {
+        self.text[..self.byte_idx(self.cursor)].width() as u16
+    }
+
+    /// Edit by a key no binding took, telling whether it was one for editing.
+    pub fn on(&mut self, key: &KeyEvent) -> bool {
+        match key.code {
+            // Unbound control keys aren't text
+            KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char(c) => self.insert(c),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.text.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.end(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Edit by a line editing action, telling whether it was one.
+    pub fn act(&mut self, action: Action) -> bool {
+        match action {
+            Action::LineStart => self.cursor = 0,
+            Action::LineEnd => self.end(),
+            Action::DeleteWord => self.delete_word(),
+            Action::ClearLine => self.clear(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn byte_idx(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map(|(i, _)| i)
+            .unwrap_or(self.text.len())
+    }
+
+    fn insert(&mut self, c: char) {
+        let idx = self.byte_idx(self.cursor);
+        self.text.insert(idx, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.delete();
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.text.chars().count() {
+            let idx = self.byte_idx(self.cursor);
+            self.text.remove(idx);
+        }
+    }
+
+    /// Delete the word before the cursor, along with any whitespace following it.
+    fn delete_word(&mut self) {
+        let chars: Vec<char> = self.text.chars().take(self.cursor).collect();
+        let trailing = chars.iter().rev().take_while(|c| c.is_whitespace()).count();
+        let word = chars
+            .iter()
+            .rev()
+            .skip(trailing)
+            .take_while(|c| !c.is_whitespace())
+            .count();
+        let start = self.byte_idx(self.cursor - trailing - word);
+        let end = self.byte_idx(self.cursor);
+        self.text.replace_range(start..end, "");
+        self.cursor -= trailing + word;
+    }
+}
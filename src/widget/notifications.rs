@@ -0,0 +1,233 @@
+use std::{
+    cmp,
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use crossterm::event::Event;
+use ratatui::{
+    layout::{Constraint, Rect},
+    style::{Color, Style, Stylize},
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Row, Table, Wrap},
+    Frame,
+};
+
+use crate::{
+    app::{Context, Mode},
+    keymap::Action,
+};
+
+use super::{centered_rect, Focusable, StatefulTable, Widget};
+
+/// How long an info message stays on screen.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+const TOAST_WIDTH: u16 = 40;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+#[derive(Clone)]
+pub struct Notice {
+    pub level: Level,
+    pub text: String,
+    pub time: DateTime<Local>,
+}
+
+impl Notice {
+    pub fn new(level: Level, text: String) -> Notice {
+        Notice {
+            level,
+            text,
+            time: Local::now(),
+        }
+    }
+}
+
+/// Everything the app has told the user: errors in a popup until acknowledged, info messages as
+/// toasts in the corner that go away on their own, and a log of both to look back at.
+pub struct Notifications {
+    pub log: StatefulTable<Notice>,
+    /// Errors not acknowledged yet, the first one shown
+    errors: VecDeque<String>,
+    toasts: Vec<(String, Instant)>,
+    /// Where to go back to once the errors are dismissed
+    return_to: Mode,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Notifications {
+            log: StatefulTable::new(),
+            errors: VecDeque::new(),
+            toasts: vec![],
+            return_to: Mode::Focus(Focusable::Emails),
+        }
+    }
+}
+
+impl Notifications {
+    pub fn push(&mut self, notice: Notice) {
+        match notice.level {
+            Level::Info => self.toasts.push((notice.text.to_owned(), Instant::now())),
+            Level::Error => self.errors.push_back(notice.text.to_owned()),
+        }
+        self.log.extend([notice]);
+    }
+
+    /// Show the next unacknowledged error, if there is one and none is shown yet.
+    pub fn show_errors(&mut self, ctx: &mut Context) {
+        if matches!(ctx.mode, Mode::Error(_)) {
+            return;
+        }
+        if let Some(e) = self.errors.front() {
+            self.return_to = ctx.mode.clone();
+            ctx.mode = Mode::Error(e.to_owned());
+        }
+    }
+
+    /// Open the log at the newest message.
+    pub fn open_log(&mut self) {
+        self.log.last();
+    }
+
+    /// Info messages in the bottom right corner, newest at the bottom.
+    pub fn draw_toasts(&mut self, f: &mut Frame, area: Rect) {
+        self.toasts.retain(|(_, t)| t.elapsed() < TOAST_DURATION);
+        let width = cmp::min(TOAST_WIDTH, area.width);
+        let mut bottom = area.bottom();
+        for (text, _) in self.toasts.iter().rev() {
+            let height = lines(text, width) + 2;
+            if bottom < area.y + height {
+                break;
+            }
+            bottom -= height;
+            let toast = Rect::new(area.right() - width, bottom, width, height);
+            f.render_widget(Clear, toast);
+            f.render_widget(
+                Paragraph::new(text.to_owned())
+                    .wrap(Wrap { trim: true })
+                    .block(
+                        Block::new()
+                            .borders(Borders::ALL)
+                            .border_type(BorderType::Rounded)
+                            .border_style(Style::new().green()),
+                    ),
+                toast,
+            );
+        }
+    }
+
+    fn draw_error(&self, f: &mut Frame, area: Rect, e: &str) {
+        let width = cmp::min(60, area.width);
+        let area = centered_rect(width, lines(e, width) + 2, area);
+        let hint = match self.errors.len() {
+            0 | 1 => "Enter to dismiss".to_owned(),
+            n => format!("Enter to dismiss, {} more", n - 1),
+        };
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(e.to_owned())
+                .wrap(Wrap { trim: true })
+                .block(
+                    Block::new()
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::new().red())
+                        .title("Error")
+                        .title_bottom(Line::from(hint).right_aligned()),
+                ),
+            area,
+        );
+    }
+
+    fn draw_log(&mut self, f: &mut Frame, area: Rect) {
+        let area = centered_rect(area.width * 4 / 5, area.height * 4 / 5, area);
+        let rows: Vec<Row> = self
+            .log
+            .items
+            .iter()
+            .map(|n| {
+                let row = Row::new([n.time.format("%H:%M:%S").to_string(), n.text.to_owned()]);
+                match n.level {
+                    Level::Info => row,
+                    Level::Error => row.red(),
+                }
+            })
+            .collect();
+        let border = Block::new()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::new().light_cyan());
+        f.render_widget(Clear, area);
+        f.render_stateful_widget(
+            Table::new(rows, [Constraint::Length(8), Constraint::Min(10)])
+                .block(border.title("Messages"))
+                .highlight_style(Style::new().bg(Color::DarkGray)),
+            area,
+            &mut self.log.state,
+        );
+    }
+}
+
+/// Roughly how many lines `text` wraps to inside a bordered box `width` wide.
+fn lines(text: &str, width: u16) -> u16 {
+    let inner = width.saturating_sub(2).max(1) as usize;
+    text.chars().count().div_ceil(inner).max(1) as u16
+}
+
+impl Widget for Notifications {
+    /// The error popup or the log, whichever is up.
+    fn draw(&mut self, f: &mut Frame, area: Rect, ctx: &mut Context) {
+        match &ctx.mode {
+            Mode::Error(e) => self.draw_error(f, area, e),
+            Mode::Focus(Focusable::Log) => self.draw_log(f, area),
+            _ => {}
+        }
+    }
+
+    fn on(&mut self, _e: Event) -> Option<Mode> {
+        None
+    }
+
+    /// Only the popup or the log get actions, told apart by whether an error is up.
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        if !self.errors.is_empty() {
+            if action != Action::Close {
+                return None;
+            }
+            self.errors.pop_front();
+            return Some(match self.errors.front() {
+                Some(e) => Mode::Error(e.to_owned()),
+                None => self.return_to.clone(),
+            });
+        }
+        match action {
+            Action::Up => self.log.next(-1),
+            Action::Down => self.log.next(1),
+            Action::First => self.log.first(),
+            Action::Last => self.log.last(),
+            Action::Close => return Some(Mode::Focus(Focusable::Emails)),
+            _ => {}
+        }
+        None
+    }
+
+    /// Only dismissing while an error is up, else moving around the log.
+    fn help(&self) -> Vec<Action> {
+        if !self.errors.is_empty() {
+            return vec![Action::Close];
+        }
+        vec![
+            Action::Down,
+            Action::Up,
+            Action::First,
+            Action::Last,
+            Action::Close,
+        ]
+    }
+}
@@ -0,0 +1,106 @@
+use std::cmp;
+
+use crossterm::event::Event;
+use ratatui::{
+    layout::{Constraint, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, BorderType, Borders, Clear, Row, Table},
+    Frame,
+};
+
+use crate::{
+    app::{Context, LoadType, Mode},
+    email::Transfer,
+    keymap::Action,
+};
+
+use super::{centered_rect, Focusable, StatefulTable, Widget};
+
+/// Popup for choosing the destination of a move or copy.
+pub struct MailboxPicker {
+    pub table: StatefulTable<String>,
+    op: Transfer,
+}
+
+impl Default for MailboxPicker {
+    fn default() -> Self {
+        MailboxPicker {
+            table: StatefulTable::new(),
+            op: Transfer::Move,
+        }
+    }
+}
+
+impl MailboxPicker {
+    pub fn open(&mut self, op: Transfer, inboxes: Vec<String>) {
+        self.op = op;
+        self.table.with_items(inboxes);
+    }
+}
+
+impl Widget for MailboxPicker {
+    fn draw(&mut self, f: &mut Frame, area: Rect, _ctx: &mut Context) {
+        let height = cmp::min(self.table.items.len() as u16 + 2, area.height / 2);
+        let area = centered_rect(40, cmp::max(height, 3), area);
+        let title = match self.op {
+            Transfer::Move => "Move to",
+            Transfer::Copy => "Copy to",
+        };
+        let rows: Vec<Row> = self
+            .table
+            .items
+            .iter()
+            .map(|i| Row::new([i.to_owned()]))
+            .collect();
+        let border = Block::new()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::new().light_cyan());
+        f.render_widget(Clear, area);
+        f.render_stateful_widget(
+            Table::new(rows, [Constraint::Percentage(100)])
+                .block(border.title(title))
+                .highlight_style(Style::new().bg(Color::DarkGray)),
+            area,
+            &mut self.table.state,
+        );
+    }
+
+    fn on(&mut self, _e: Event) -> Option<Mode> {
+        None
+    }
+
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        match action {
+            Action::Up => self.table.next(-1),
+            Action::Down => self.table.next(1),
+            Action::First => self.table.first(),
+            Action::Last => self.table.last(),
+            Action::Select => {
+                let dest = self
+                    .table
+                    .state
+                    .selected()
+                    .and_then(|i| self.table.items.get(i));
+                return Some(match dest {
+                    Some(dest) => Mode::Loading(LoadType::Transfer(self.op, dest.to_owned())),
+                    None => Mode::Focus(Focusable::Emails),
+                });
+            }
+            Action::Close => return Some(Mode::Focus(Focusable::Emails)),
+            _ => {}
+        }
+        None
+    }
+
+    fn help(&self) -> Vec<Action> {
+        vec![
+            Action::Down,
+            Action::Up,
+            Action::First,
+            Action::Last,
+            Action::Select,
+            Action::Close,
+        ]
+    }
+}
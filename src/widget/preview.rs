@@ -1,7 +1,6 @@
-use core::time;
-use std::{borrow::Borrow, cmp, ops::Deref};
+use std::cmp;
 
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, MouseEvent, MouseEventKind};
+use crossterm::event::{Event, MouseEvent, MouseEventKind};
 use html2text::{
     render::text_renderer::{RichAnnotation, RichDecorator, TaggedLine, TextDecorator},
     RenderTree,
@@ -15,7 +14,11 @@ use ratatui::{
 };
 use regex::Regex;
 
-use crate::app::{Context, LoadType, Mode};
+use crate::{
+    app::{Context, LoadType, Mode},
+    compose::ReplyKind,
+    keymap::Action,
+};
 
 use super::{focus_border, Focusable, Widget};
 
@@ -80,15 +83,18 @@ pub struct Preview {
     last_area: Rect,
     redraw: bool,
     lines: Vec<StyledLine>,
+    /// Account, inbox, UID and UIDVALIDITY of the message shown, which replies go to even if the
+    /// list selection moved on meanwhile
+    message: Option<(usize, String, u32, u32)>,
 }
 
 fn raw_to_lines(body: &Lines) -> Vec<StyledLine> {
     // let body = content.render_rich(width).unwrap().into_lines().unwrap();
     let mut lines: Vec<StyledLine> = vec![];
+    let re = Regex::new(r"\s").unwrap();
     for line in body {
         let mut spans: StyledLine = vec![];
         for ts in line.tagged_strings() {
-            let re = Regex::new(r"\s").unwrap();
             let s = re.replace_all(&ts.s, " ").to_string();
             // let s = ts.s.replace(['\u{200C}', '\u{200B}'], " ");
             let mut style = Style::new();
@@ -126,11 +132,16 @@ fn raw_to_lines(body: &Lines) -> Vec<StyledLine> {
 }
 
 impl Preview {
-    pub fn set_content(&mut self, raw_html: String) {
+    pub fn set_content(&mut self, message: (usize, String, u32, u32), raw_html: String) {
+        self.message = Some(message);
         self.content = html2text::parse(raw_html.as_bytes()).ok();
         self.redraw = true;
         self.state = 0;
     }
+
+    pub fn message(&self) -> Option<&(usize, String, u32, u32)> {
+        self.message.as_ref()
+    }
 }
 
 impl Widget for Preview {
@@ -151,10 +162,9 @@ impl Widget for Preview {
         let border = focus_border(ctx, Focusable::Preview)
             .borders(Borders::TOP | Borders::BOTTOM | Borders::RIGHT)
             .border_type(BorderType::Rounded);
-        let title = match ctx.mode == Mode::Loading(LoadType::FetchPreview) {
-            true => "Loading...",
-            false => "Preview",
-        };
+        let title = ctx
+            .loading_title(&LoadType::FetchPreview)
+            .unwrap_or("Preview".to_owned());
         f.render_widget(
             Paragraph::new(to_lines(self.lines.to_owned()))
                 .scroll((self.state, 0))
@@ -181,33 +191,18 @@ impl Widget for Preview {
     }
 
     fn on(&mut self, e: Event) -> Option<Mode> {
-        if let Event::Key(KeyEvent {
-            code,
-            kind: KeyEventKind::Press,
-            ..
-        }) = e
-        {
-            match code {
-                KeyCode::Char('k') => self.state = cmp::max(1, self.state) - 1,
-                KeyCode::Char('j') => {
-                    self.state = cmp::min(cmp::max(1, self.content_length) - 1, self.state + 1)
-                }
-                _ => {}
-            }
-        } else if let Event::Mouse(MouseEvent {
+        if let Event::Mouse(MouseEvent {
             kind, row, column, ..
         }) = e
         {
             match kind {
-                MouseEventKind::ScrollUp => {
-                    if self.last_area.contains(Position::new(column, row)) {
-                        self.state = cmp::max(2, self.state) - 2;
-                    }
+                MouseEventKind::ScrollUp if self.last_area.contains(Position::new(column, row)) => {
+                    self.state = cmp::max(2, self.state) - 2;
                 }
-                MouseEventKind::ScrollDown => {
-                    if self.last_area.contains(Position::new(column, row)) {
-                        self.state = cmp::min(cmp::max(1, self.content_length) - 1, self.state + 2);
-                    }
+                MouseEventKind::ScrollDown
+                    if self.last_area.contains(Position::new(column, row)) =>
+                {
+                    self.state = cmp::min(cmp::max(1, self.content_length) - 1, self.state + 2);
                 }
                 _ => {}
             }
@@ -215,7 +210,30 @@ impl Widget for Preview {
         None
     }
 
-    fn help(self) -> Option<(&'static str, &'static str)> {
-        todo!()
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        let reply = |kind| Some(Mode::Loading(LoadType::Reply(kind, Focusable::Preview)));
+        match action {
+            Action::Up => self.state = cmp::max(1, self.state) - 1,
+            Action::Down => {
+                self.state = cmp::min(cmp::max(1, self.content_length) - 1, self.state + 1)
+            }
+            Action::Reply => return reply(ReplyKind::Reply),
+            Action::ReplyAll => return reply(ReplyKind::ReplyAll),
+            Action::Forward => return reply(ReplyKind::Forward),
+            Action::ForwardAttached => return reply(ReplyKind::ForwardAttached),
+            _ => {}
+        }
+        None
+    }
+
+    fn help(&self) -> Vec<Action> {
+        vec![
+            Action::Down,
+            Action::Up,
+            Action::Reply,
+            Action::ReplyAll,
+            Action::Forward,
+            Action::ForwardAttached,
+        ]
     }
 }
@@ -1,3 +1,7 @@
+use crate::{
+    app::{Context, LoadType, Mode},
+    keymap::Action,
+};
 use crossterm::event::{Event, KeyEvent, KeyEventKind};
 use ratatui::{
     layout::Rect,
@@ -5,53 +9,93 @@ use ratatui::{
     Frame,
 };
 
-use crate::app::{Context, Mode};
+use super::{focus_border, input::Input, Focusable, Widget};
 
-use super::{focus_border, Focusable, Widget};
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchKind {
+    /// Query the server, see [`crate::query`]
+    #[default]
+    Server,
+    /// Narrow the loaded message list as the query is typed
+    Filter,
+}
 
+#[derive(Default)]
 pub struct Search {
-    pub input: String,
+    pub input: Input,
+    pub kind: SearchKind,
 }
 
-impl Default for Search {
-    fn default() -> Self {
-        Search {
-            input: "Testing :)".to_owned(),
+impl Search {
+    /// Start editing a query of `kind`, continuing the previous one if it was of the same kind.
+    pub fn open(&mut self, kind: SearchKind) {
+        if kind != self.kind {
+            self.kind = kind;
+            self.input.clear();
         }
+        self.input.end();
     }
 }
 
-impl Search {
-    // pub fn set_input(&mut self, input: String) {
-    //     self.input = input;
-    // }
-}
-
 impl Widget for Search {
     fn draw(&mut self, f: &mut Frame, area: Rect, ctx: &mut Context) {
         let border = focus_border(ctx, Focusable::Search).border_type(BorderType::Rounded);
         f.render_widget(
-            Paragraph::new(ctx.errors.front().map(|x| x.to_owned()).unwrap_or_default())
-                .block(border.title("Search")),
+            Paragraph::new(self.input.as_str().to_owned()).block(border.title(match self.kind {
+                SearchKind::Server => "Search",
+                SearchKind::Filter => "Filter",
+            })),
             area,
         );
+        if ctx.mode == Mode::Focus(Focusable::Search) {
+            f.set_cursor(area.x + 1 + self.input.cursor_width(), area.y + 1);
+        }
     }
 
+    /// Whatever isn't bound to an action edits the query.
     fn on(&mut self, e: Event) -> Option<Mode> {
-        if let Event::Key(KeyEvent {
-            // code,
-            kind: KeyEventKind::Press,
-            ..
-        }) = e
+        if let Event::Key(
+            key @ KeyEvent {
+                kind: KeyEventKind::Press,
+                ..
+            },
+        ) = e
         {
-            // match code {
-            //     _ => {}
-            // }
+            self.input.on(&key);
+        }
+        None
+    }
+
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        if self.input.act(action) {
+            return None;
+        }
+        match action {
+            Action::Submit => {
+                return Some(match self.kind {
+                    SearchKind::Server => Mode::Loading(LoadType::Search),
+                    SearchKind::Filter => Mode::Focus(Focusable::Emails),
+                });
+            }
+            Action::Close => {
+                if self.kind == SearchKind::Filter {
+                    self.input.clear();
+                }
+                return Some(Mode::Focus(Focusable::Emails));
+            }
+            _ => {}
         }
         None
     }
 
-    fn help(self) -> Option<(&'static str, &'static str)> {
-        todo!()
+    fn help(&self) -> Vec<Action> {
+        vec![
+            Action::Submit,
+            Action::Close,
+            Action::LineStart,
+            Action::LineEnd,
+            Action::DeleteWord,
+            Action::ClearLine,
+        ]
     }
 }
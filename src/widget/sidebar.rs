@@ -1,31 +1,148 @@
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::Event;
 use ratatui::{
     layout::{Constraint, Rect},
-    style::{Color, Style},
+    style::{Color, Style, Stylize},
     widgets::{BorderType, Borders, Row, Table},
     Frame,
 };
 
-use crate::app::{Context, LoadType, Mode};
+use crate::{
+    app::{Context, LoadType, Mode},
+    email::{Inbox, SpecialUse},
+    keymap::Action,
+};
 
 use super::{focus_border, Focusable, StatefulTable, Widget};
 
+#[derive(Clone, PartialEq, Eq)]
+pub enum Folder {
+    /// Every account's INBOX together
+    All,
+    /// An account's INBOX
+    Account(usize),
+    Mailbox(usize, String),
+}
+
 pub struct Sidebar {
-    pub table: StatefulTable<String>,
+    pub table: StatefulTable<Folder>,
+    accounts: Vec<String>,
+    /// Mailboxes of each account
+    inboxes: Vec<Vec<Inbox>>,
 }
 
 impl Default for Sidebar {
     fn default() -> Self {
         Sidebar {
             table: StatefulTable::new(),
+            accounts: vec![],
+            inboxes: vec![],
         }
     }
 }
 
 impl Sidebar {
-    pub fn set_inboxes(&mut self, cats: Vec<String>) {
-        self.table.items = cats;
-        self.table.select(1);
+    /// Show the accounts named `accounts`, as groups of their mailboxes when there are several.
+    pub fn set_accounts(&mut self, accounts: Vec<String>) {
+        self.inboxes = vec![vec![]; accounts.len()];
+        self.accounts = accounts;
+        self.rebuild();
+    }
+
+    pub fn set_inboxes(&mut self, account: usize, cats: Vec<Inbox>) {
+        if let Some(inboxes) = self.inboxes.get_mut(account) {
+            *inboxes = cats;
+        }
+        self.rebuild();
+    }
+
+    /// Lay the rows out again, keeping the same folder selected, or else the first INBOX.
+    fn rebuild(&mut self) {
+        let selected = self.selected().cloned();
+        let grouped = self.accounts.len() > 1;
+        let mut items = vec![];
+        if grouped {
+            items.push(Folder::All);
+        }
+        for (account, inboxes) in self.inboxes.iter().enumerate() {
+            if grouped {
+                items.push(Folder::Account(account));
+            }
+            items.extend(
+                inboxes
+                    .iter()
+                    .map(|i| Folder::Mailbox(account, i.name.to_owned())),
+            );
+        }
+        self.table.with_items(items);
+        let select = selected
+            .and_then(|f| self.table.items.iter().position(|i| *i == f))
+            .or_else(|| {
+                self.table.items.iter().position(|i| match i {
+                    Folder::Mailbox(_, name) => name.eq_ignore_ascii_case("INBOX"),
+                    _ => true,
+                })
+            });
+        self.table.select(select.unwrap_or_default());
+    }
+
+    pub fn selected(&self) -> Option<&Folder> {
+        self.table
+            .state
+            .selected()
+            .and_then(|i| self.table.items.get(i))
+    }
+
+    /// The account of the selected folder, the first for all inboxes.
+    pub fn account(&self) -> usize {
+        match self.selected() {
+            Some(Folder::Account(a) | Folder::Mailbox(a, _)) => *a,
+            _ => 0,
+        }
+    }
+
+    /// Select `folder`, if it's listed.
+    pub fn select(&mut self, folder: &Folder) -> bool {
+        match self.table.items.iter().position(|f| f == folder) {
+            Some(i) => {
+                self.table.select(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Select the INBOX of `account`, as the account's header when there are several.
+    pub fn select_account(&mut self, account: usize) -> bool {
+        let i = self.table.items.iter().position(|f| match f {
+            Folder::Account(a) => *a == account,
+            Folder::Mailbox(a, name) => *a == account && name.eq_ignore_ascii_case("INBOX"),
+            Folder::All => false,
+        });
+        match i {
+            Some(i) => {
+                self.table.select(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn names(&self, account: usize) -> Vec<String> {
+        self.inboxes
+            .get(account)
+            .into_iter()
+            .flatten()
+            .map(|i| i.name.to_owned())
+            .collect()
+    }
+
+    /// The mailbox the server advertises for `special_use`, if any.
+    pub fn special_use(&self, account: usize, special_use: SpecialUse) -> Option<String> {
+        self.inboxes
+            .get(account)?
+            .iter()
+            .find(|i| i.special_use == Some(special_use))
+            .map(|i| i.name.to_owned())
     }
 }
 
@@ -41,19 +158,38 @@ impl Widget for Sidebar {
         //         false => Line::from(l.to_owned()),
         //     })
         //     .collect::<Vec<Line>>();
+        let grouped = self.accounts.len() > 1;
         let lines: Vec<Row> = self
             .table
             .items
             .iter()
-            .map(|i| Row::new([i.to_owned()]))
+            .map(|i| match i {
+                Folder::All => Row::new(["All inboxes"]).bold(),
+                Folder::Account(a) => {
+                    let name = match ctx.workers.state(*a).label() {
+                        Some(state) => format!("{} ({})", self.accounts[*a], state),
+                        None => self.accounts[*a].to_owned(),
+                    };
+                    Row::new([name]).bold().light_cyan()
+                }
+                Folder::Mailbox(_, name) if grouped => Row::new([format!("  {}", name)]),
+                Folder::Mailbox(_, name) => Row::new([name.to_owned()]),
+            })
             .collect();
         let border = focus_border(ctx, Focusable::Sidebar)
             .borders(Borders::LEFT | Borders::TOP | Borders::BOTTOM)
             .border_type(BorderType::Rounded);
-        let title = match ctx.mode == Mode::Loading(LoadType::FetchInboxes) {
-            true => "Loading...",
-            false => "Inboxes",
+        // Shown on the account headers instead when there are several
+        let state = match grouped {
+            true => None,
+            false => ctx.workers.state(0).label(),
         };
+        let title = ctx
+            .loading_title(&LoadType::FetchInboxes)
+            .unwrap_or(match state {
+                Some(state) => format!("Inboxes ({})", state),
+                None => "Inboxes".to_owned(),
+            });
         f.render_stateful_widget(
             Table::new(lines, [Constraint::Percentage(100)])
                 .block(border.title(title))
@@ -63,30 +199,21 @@ impl Widget for Sidebar {
         );
     }
 
-    fn on(&mut self, e: Event) -> Option<Mode> {
-        if let Event::Key(KeyEvent {
-            code,
-            kind: KeyEventKind::Press,
-            ..
-        }) = e
-        {
-            match code {
-                KeyCode::Char('k') => {
-                    self.table.next(-1);
-                }
-                KeyCode::Char('j') => {
-                    self.table.next(1);
-                }
-                KeyCode::Enter => {
-                    return Some(Mode::Loading(LoadType::FetchEmails));
-                }
-                _ => {}
-            }
+    fn on(&mut self, _e: Event) -> Option<Mode> {
+        None
+    }
+
+    fn act(&mut self, action: Action) -> Option<Mode> {
+        match action {
+            Action::Up => self.table.next(-1),
+            Action::Down => self.table.next(1),
+            Action::Select => return Some(Mode::Loading(LoadType::FetchEmails)),
+            _ => {}
         }
         None
     }
 
-    fn help(self) -> Option<(&'static str, &'static str)> {
-        todo!()
+    fn help(&self) -> Vec<Action> {
+        vec![Action::Down, Action::Up, Action::Select]
     }
 }
@@ -0,0 +1,904 @@
+use std::{
+    cmp,
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use lettre::Message;
+
+use crate::{
+    app::LoadType,
+    cache::{Cache, Changes, Synced},
+    config::Account,
+    connection,
+    email::{self, ImapSession, Inbox, MailFlag, Tracking, Transfer},
+    error::{self, Error, Result},
+    smtp,
+    widget::emails::{EmailEntry, Flags, Listing},
+};
+
+/// Number of messages fetched per round trip while loading a mailbox. Smaller chunks give more
+/// frequent progress updates and more chances to cancel.
+const FETCH_CHUNK: u32 = 10;
+/// How often an otherwise unused session sends NOOP, so that the server doesn't log it out and a
+/// dropped connection is noticed before the user next needs it.
+const KEEPALIVE: Duration = Duration::from_secs(120);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Not logged in yet
+    Connecting,
+    Online,
+    /// The connection dropped and is being restored
+    Reconnecting,
+    /// Logging in failed, or getting a lost connection back did so far
+    Offline,
+}
+
+impl ConnectionState {
+    /// How to describe the state next to an account, nothing when all is well.
+    pub fn label(&self) -> Option<&'static str> {
+        match self {
+            ConnectionState::Connecting => Some("connecting"),
+            ConnectionState::Online => None,
+            ConnectionState::Reconnecting => Some("reconnecting"),
+            ConnectionState::Offline => Some("offline"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Command {
+    Login(Box<Account>),
+    FetchInboxes,
+    FetchEmails {
+        inbox: String,
+        n: u32,
+    },
+    FetchPreview {
+        inbox: String,
+        uid: u32,
+        uid_validity: u32,
+    },
+    /// The whole of a message, for replying to or forwarding it
+    FetchMessage {
+        inbox: String,
+        uid: u32,
+        uid_validity: u32,
+    },
+    Search {
+        inbox: String,
+        criteria: String,
+        n: usize,
+    },
+    /// The next page of a listing: up to `n` messages older than `before_uid`, matching
+    /// `criteria` if the listing is a search
+    FetchMore {
+        inbox: String,
+        uid_validity: u32,
+        criteria: Option<String>,
+        before_uid: u32,
+        n: usize,
+    },
+    SetFlag {
+        inbox: String,
+        uid_validity: u32,
+        uids: Vec<u32>,
+        flag: MailFlag,
+        add: bool,
+    },
+    Expunge {
+        inbox: String,
+    },
+    Transfer {
+        inbox: String,
+        uid_validity: u32,
+        uids: Vec<u32>,
+        dest: String,
+        op: Transfer,
+    },
+    /// Send a message over SMTP, keeping a copy in the account's `sent_folder` if it has one
+    Send {
+        account: Box<Account>,
+        message: Box<Message>,
+    },
+    Logout,
+}
+
+impl Command {
+    /// Whether running the command again after it failed midway does no harm. A move or copy may
+    /// have been carried out before the connection dropped, and a message may have been sent,
+    /// so those are reported failed rather than done twice.
+    fn replayable(&self) -> bool {
+        !matches!(
+            self,
+            Command::Login(_) | Command::Logout | Command::Transfer { .. } | Command::Send { .. }
+        )
+    }
+}
+
+pub enum WorkerEvent {
+    LoggedIn,
+    Inboxes(Vec<Inbox>),
+    /// The first page of `listing`
+    Emails {
+        entries: Vec<EmailEntry>,
+        listing: Listing,
+    },
+    MoreEmails(Vec<EmailEntry>),
+    Preview {
+        inbox: String,
+        uid: u32,
+        uid_validity: u32,
+        html: String,
+    },
+    /// A message as fetched, in RFC 822 form
+    Message {
+        inbox: String,
+        uid: u32,
+        raw: Vec<u8>,
+    },
+    Flags {
+        inbox: String,
+        flags: Vec<(u32, Flags)>,
+    },
+    Expunged {
+        inbox: String,
+    },
+    Transferred {
+        inbox: String,
+        uids: Vec<u32>,
+        op: Transfer,
+        dest: String,
+    },
+    /// A message went out. Whether a copy was kept, in which mailbox, if one is configured
+    Sent {
+        saved: Option<Result<String>>,
+    },
+    Progress {
+        done: usize,
+        total: usize,
+    },
+    /// Sent by the worker on its own when the connection drops or comes back
+    State(ConnectionState),
+    Error(Error),
+}
+
+struct Request {
+    id: u64,
+    load: LoadType,
+    cmd: Command,
+    cancel: Arc<AtomicBool>,
+}
+
+struct Response {
+    id: u64,
+    load: LoadType,
+    event: WorkerEvent,
+}
+
+struct Pending {
+    load: LoadType,
+    cancel: Arc<AtomicBool>,
+    progress: Option<(usize, usize)>,
+}
+
+/// Handle to the background thread that owns the IMAP session.
+///
+/// Commands are queued with [`Worker::send`] and answered asynchronously; results are collected
+/// with [`Worker::try_recv`] from the UI loop without ever blocking on the network.
+pub struct Worker {
+    tx: Option<Sender<Request>>,
+    rx: Receiver<Response>,
+    handle: Option<JoinHandle<()>>,
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+    state: ConnectionState,
+}
+
+impl Worker {
+    pub fn spawn() -> Worker {
+        let (req_tx, req_rx) = mpsc::channel::<Request>();
+        let (res_tx, res_rx) = mpsc::channel::<Response>();
+        let handle = thread::Builder::new()
+            .name("imap-worker".to_owned())
+            .spawn(move || run(req_rx, res_tx))
+            .expect("failed to spawn imap worker");
+        Worker {
+            tx: Some(req_tx),
+            rx: res_rx,
+            handle: Some(handle),
+            next_id: 0,
+            pending: HashMap::new(),
+            state: ConnectionState::Connecting,
+        }
+    }
+
+    /// Queue a command. If `load` is a fetch, a still pending fetch of the same kind is cancelled
+    /// since its result would be stale by the time it arrives. Actions are never dropped.
+    pub fn send(&mut self, load: LoadType, cmd: Command) {
+        if load.supersedes() {
+            self.cancel(&load);
+        }
+        self.next_id += 1;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let req = Request {
+            id: self.next_id,
+            load: load.clone(),
+            cmd,
+            cancel: cancel.clone(),
+        };
+        if let Some(tx) = &self.tx {
+            if tx.send(req).is_ok() {
+                self.pending.insert(
+                    self.next_id,
+                    Pending {
+                        load,
+                        cancel,
+                        progress: None,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn cancel(&mut self, load: &LoadType) {
+        self.pending.retain(|_, p| {
+            if &p.load == load {
+                p.cancel.store(true, Ordering::Relaxed);
+            }
+            &p.load != load
+        });
+    }
+
+    pub fn cancel_all(&mut self) {
+        for (_, p) in self.pending.drain() {
+            p.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_loading(&self, load: &LoadType) -> bool {
+        self.pending.values().any(|p| &p.load == load)
+    }
+
+    pub fn progress(&self, load: &LoadType) -> Option<(usize, usize)> {
+        self.pending
+            .values()
+            .find(|p| &p.load == load)
+            .and_then(|p| p.progress)
+    }
+
+    /// Returns the next finished result, if any. Progress updates are folded into the pending
+    /// state and results of cancelled or superseded commands are dropped.
+    pub fn try_recv(&mut self) -> Option<(LoadType, WorkerEvent)> {
+        while let Ok(res) = self.rx.try_recv() {
+            match (&res.load, &res.event) {
+                (_, WorkerEvent::State(state)) => {
+                    self.state = *state;
+                    return Some((res.load, res.event));
+                }
+                (_, WorkerEvent::LoggedIn) => self.state = ConnectionState::Online,
+                (LoadType::Login, WorkerEvent::Error(_)) => self.state = ConnectionState::Offline,
+                _ => {}
+            }
+            let Some(pending) = self.pending.get_mut(&res.id) else {
+                continue;
+            };
+            if let WorkerEvent::Progress { done, total } = res.event {
+                pending.progress = Some((done, total));
+                continue;
+            }
+            self.pending.remove(&res.id);
+            return Some((res.load, res.event));
+        }
+        None
+    }
+
+    /// Cancel everything in flight and have the worker log out and exit.
+    fn logout(&mut self) {
+        self.cancel_all();
+        self.send(LoadType::Login, Command::Logout);
+        self.tx = None;
+    }
+
+    fn join(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// One [`Worker`] per account, indexed like [`Config::accounts`](crate::config::Config::accounts).
+/// Loads are tracked across all of them, so that e.g. a fetch shows as loading while any account
+/// is still at it.
+pub struct Workers(Vec<Worker>);
+
+impl Workers {
+    pub fn spawn(accounts: usize) -> Workers {
+        Workers((0..accounts).map(|_| Worker::spawn()).collect())
+    }
+
+    pub fn send(&mut self, account: usize, load: LoadType, cmd: Command) {
+        if let Some(w) = self.0.get_mut(account) {
+            w.send(load, cmd);
+        }
+    }
+
+    pub fn cancel(&mut self, load: &LoadType) {
+        self.0.iter_mut().for_each(|w| w.cancel(load));
+    }
+
+    pub fn cancel_all(&mut self) {
+        self.0.iter_mut().for_each(|w| w.cancel_all());
+    }
+
+    /// Cancel everything queued for `account` alone.
+    pub fn cancel_account(&mut self, account: usize) {
+        if let Some(w) = self.0.get_mut(account) {
+            w.cancel_all();
+        }
+    }
+
+    pub fn is_loading(&self, load: &LoadType) -> bool {
+        self.0.iter().any(|w| w.is_loading(load))
+    }
+
+    pub fn state(&self, account: usize) -> ConnectionState {
+        self.0
+            .get(account)
+            .map_or(ConnectionState::Offline, |w| w.state)
+    }
+
+    /// Progress of `load`, summed over the accounts reporting any.
+    pub fn progress(&self, load: &LoadType) -> Option<(usize, usize)> {
+        self.0
+            .iter()
+            .filter_map(|w| w.progress(load))
+            .reduce(|(d1, t1), (d2, t2)| (d1 + d2, t1 + t2))
+    }
+
+    /// Like [`Worker::try_recv`], along with the index of the account the result is for, which
+    /// is also filled in on any entries.
+    pub fn try_recv(&mut self) -> Option<(usize, LoadType, WorkerEvent)> {
+        self.0.iter_mut().enumerate().find_map(|(i, w)| {
+            let (load, mut evt) = w.try_recv()?;
+            match &mut evt {
+                WorkerEvent::Emails { entries, listing } => {
+                    listing.account = i;
+                    entries.iter_mut().for_each(|e| e.account = i);
+                }
+                WorkerEvent::MoreEmails(entries) => entries.iter_mut().for_each(|e| e.account = i),
+                _ => {}
+            }
+            Some((i, load, evt))
+        })
+    }
+
+    /// Log out of every account, then wait for all of the workers to exit.
+    pub fn shutdown(&mut self) {
+        self.0.iter_mut().for_each(|w| w.logout());
+        self.0.iter_mut().for_each(|w| w.join());
+    }
+}
+
+fn run(rx: Receiver<Request>, tx: Sender<Response>) {
+    let mut session: Option<ImapSession> = None;
+    let mut cache: Option<Cache> = None;
+    let mut account: Option<Account> = None;
+    // While the session is lost: when to try getting it back next, and the wait before that
+    let mut retry: Option<(Instant, Duration)> = None;
+    let state = |state| {
+        let _ = tx.send(Response {
+            id: 0,
+            load: LoadType::Login,
+            event: WorkerEvent::State(state),
+        });
+    };
+    loop {
+        let timeout = match retry {
+            Some((at, _)) => at.saturating_duration_since(Instant::now()),
+            None => KEEPALIVE,
+        };
+        let req = match rx.recv_timeout(timeout) {
+            Ok(req) => req,
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                if retry.is_some() || lost(&mut session, &state) {
+                    retry = reconnect(&mut session, &account, retry, &state);
+                }
+                continue;
+            }
+        };
+        if req.cancel.load(Ordering::Relaxed) {
+            continue;
+        }
+        // While offline, commands are answered from the cache or fail right away, the backoff
+        // timer alone trying to reconnect, as that may block for a while
+        if let Command::Login(a) = &req.cmd {
+            account = Some(*a.clone());
+            retry = None;
+        }
+        let logout = matches!(req.cmd, Command::Logout);
+        let login = matches!(req.cmd, Command::Login(_));
+        let respond = |event: WorkerEvent| {
+            let _ = tx.send(Response {
+                id: req.id,
+                load: req.load.clone(),
+                event,
+            });
+        };
+        let cmd = req.cmd.clone();
+        let mut result = catch(&mut session, |session| {
+            handle(session, &mut cache, cmd, &req.cancel, &respond)
+        });
+        // Retried once on a fresh connection, which selects the mailbox again as every command
+        // selects the one it needs
+        let network = matches!(result, Err(Error::Network(_)));
+        if network && !logout && lost(&mut session, &state) {
+            retry = reconnect(&mut session, &account, None, &state);
+            if session.is_some() && req.cmd.replayable() && !req.cancel.load(Ordering::Relaxed) {
+                result = catch(&mut session, |session| {
+                    handle(session, &mut cache, req.cmd, &req.cancel, &respond)
+                });
+            }
+        }
+        // Not reaching the server at first is no reason to give up, unlike a refused login
+        if login && matches!(result, Err(Error::Network(_))) {
+            let wait = connection::backoff(None);
+            retry = Some((Instant::now() + wait, wait));
+        }
+        match result {
+            Ok(Some(event)) => respond(event),
+            Ok(None) => {}
+            Err(e) => respond(WorkerEvent::Error(e)),
+        }
+        if logout {
+            break;
+        }
+    }
+}
+
+/// Run `f`, turning a panic into an error. The session may be left mid-response, so it is
+/// dropped, to be reconnected like a lost one.
+fn catch<T>(
+    session: &mut Option<ImapSession>,
+    f: impl FnOnce(&mut Option<ImapSession>) -> Result<T>,
+) -> Result<T> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(session)));
+    result.unwrap_or_else(|payload| {
+        *session = None;
+        Err(error::panicked(payload))
+    })
+}
+
+/// Whether the session broke, as opposed to the server refusing a command, in which case it is
+/// dropped.
+fn lost(session: &mut Option<ImapSession>, state: &dyn Fn(ConnectionState)) -> bool {
+    let Some(s) = session else {
+        return false;
+    };
+    if s.noop().is_ok() {
+        return false;
+    }
+    *session = None;
+    state(ConnectionState::Reconnecting);
+    true
+}
+
+/// Try once to log back in to `account`. Returns when to try again if that failed, waiting twice
+/// as long as after the `previous` attempt.
+fn reconnect(
+    session: &mut Option<ImapSession>,
+    account: &Option<Account>,
+    previous: Option<(Instant, Duration)>,
+    state: &dyn Fn(ConnectionState),
+) -> Option<(Instant, Duration)> {
+    let account = account.as_ref()?;
+    match open(account.clone()) {
+        Ok(s) => {
+            *session = Some(s);
+            state(ConnectionState::Online);
+            None
+        }
+        Err(_) => {
+            let wait = connection::backoff(previous.map(|(_, wait)| wait));
+            state(ConnectionState::Offline);
+            Some((Instant::now() + wait, wait))
+        }
+    }
+}
+
+/// Log in to `account`, with QRESYNC on for [`sync`] to use.
+fn open(account: Account) -> Result<ImapSession> {
+    let mut s = email::new_session(account)?;
+    email::enable_qresync(&mut s)?;
+    Ok(s)
+}
+
+/// Fetch the envelopes of `uids` a chunk at a time, reporting progress. Returns `None` when
+/// cancelled.
+fn fetch_uids(
+    s: &mut ImapSession,
+    uids: &[u32],
+    inbox: &str,
+    uid_validity: u32,
+    cancel: &AtomicBool,
+    respond: &dyn Fn(WorkerEvent),
+) -> Result<Option<Vec<EmailEntry>>> {
+    let mut entries = Vec::with_capacity(uids.len());
+    for chunk in uids.chunks(FETCH_CHUNK as usize) {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let chunk = email::uid_fetch_envelopes(s, chunk, inbox, uid_validity)?;
+        entries.extend(chunk);
+        respond(WorkerEvent::Progress {
+            done: entries.len(),
+            total: uids.len(),
+        });
+    }
+    Ok(Some(entries))
+}
+
+enum Sync {
+    Changes(Changes),
+    /// More new messages than a page, so the cache is better started over
+    TooFarBehind,
+    Cancelled,
+}
+
+/// Find out what changed in the selected mailbox of `listing` since it was `synced`: messages
+/// above the cached UIDs arrived, cached UIDs the server no longer has vanished, and flags are
+/// compared for the cached range, only those changed since the last sync if the server has
+/// CONDSTORE. With QRESYNC the server says which messages vanished too, otherwise the cached UIDs
+/// are compared with those it still has.
+fn sync(
+    s: &mut ImapSession,
+    tracking: Tracking,
+    synced: &Synced,
+    listing: &Listing,
+    n: u32,
+    cancel: &AtomicBool,
+    respond: &dyn Fn(WorkerEvent),
+) -> Result<Sync> {
+    let (Some(&lo), Some(&hi)) = (synced.uids.first(), synced.uids.last()) else {
+        return Ok(Sync::TooFarBehind);
+    };
+    // `hi+1:*` is never empty, it includes the highest UID even if that is below `hi`
+    let mut arrived = email::search(s, format!("UID {}:*", hi + 1))?;
+    arrived.retain(|uid| *uid > hi);
+    if arrived.len() > n as usize {
+        return Ok(Sync::TooFarBehind);
+    }
+    let (flags, vanished) = match (tracking, synced.highest_modseq) {
+        (Tracking::QResync, Some(modseq)) => {
+            let changed = email::fetch_changes(s, lo, hi, modseq)?;
+            let vanished = synced
+                .uids
+                .iter()
+                .filter(|uid| changed.vanished.iter().any(|r| r.contains(uid)))
+                .copied()
+                .collect();
+            (changed.flags, vanished)
+        }
+        (_, modseq) => {
+            let present = email::search(s, format!("UID {}:{}", lo, hi))?;
+            let vanished = synced
+                .uids
+                .iter()
+                // Newest first, so reversed
+                .filter(|uid| present.binary_search_by(|p| p.cmp(uid).reverse()).is_err())
+                .copied()
+                .collect();
+            (email::fetch_flags(s, lo, hi, modseq)?, vanished)
+        }
+    };
+    let Some(arrived) = fetch_uids(
+        s,
+        &arrived,
+        &listing.inbox,
+        listing.uid_validity,
+        cancel,
+        respond,
+    )?
+    else {
+        return Ok(Sync::Cancelled);
+    };
+    Ok(Sync::Changes(Changes {
+        arrived,
+        vanished,
+        flags,
+        highest_modseq: None,
+    }))
+}
+
+fn logged_in(session: &mut Option<ImapSession>) -> Result<&mut ImapSession> {
+    session
+        .as_mut()
+        .ok_or_else(|| Error::Network("not connected".to_owned()))
+}
+
+/// The raw message `uid`, from the cache if it's there, marked as read either way when online.
+/// Offline the cache keeps the flag the server has, or the message would stay unread there while
+/// the cache, only told about later changes, went on saying it was read.
+fn fetch_body(
+    session: &mut Option<ImapSession>,
+    cache: &Option<Cache>,
+    inbox: &str,
+    uid: u32,
+    uid_validity: u32,
+) -> Result<Option<Vec<u8>>> {
+    let cached = cache
+        .as_ref()
+        .and_then(|c| c.body(inbox, uid_validity, uid));
+    let body = match cached {
+        Some(body) => {
+            let Some(s) = session.as_mut() else {
+                return Ok(Some(body));
+            };
+            // Fetching the body would have marked it read, so do the same
+            email::set_flag(
+                s,
+                inbox.to_owned(),
+                uid_validity,
+                &[uid],
+                MailFlag::Seen,
+                true,
+            )?;
+            Some(body)
+        }
+        None => {
+            let body = email::fetch_body(logged_in(session)?, inbox.to_owned(), uid, uid_validity)?;
+            if let (Some(c), Some(body)) = (cache, &body) {
+                c.store_body(inbox, uid_validity, uid, body);
+            }
+            body
+        }
+    };
+    if let Some(c) = cache {
+        c.mark_seen(inbox, uid_validity, uid);
+    }
+    Ok(body)
+}
+
+/// Carry out `cmd`. Fetches are answered from the cache when not logged in, and their results
+/// stored there otherwise.
+fn handle(
+    session: &mut Option<ImapSession>,
+    cache: &mut Option<Cache>,
+    cmd: Command,
+    cancel: &AtomicBool,
+    respond: &dyn Fn(WorkerEvent),
+) -> Result<Option<WorkerEvent>> {
+    match cmd {
+        Command::Login(account) => {
+            if let Some(mut s) = session.take() {
+                s.logout()?;
+            }
+            // Before connecting, so that the cache is there to fall back on if that fails
+            *cache = Cache::open(&account);
+            *session = Some(open(*account)?);
+            Ok(Some(WorkerEvent::LoggedIn))
+        }
+        Command::Logout => {
+            if let Some(mut s) = session.take() {
+                s.logout()?;
+            }
+            Ok(None)
+        }
+        Command::FetchInboxes => {
+            if session.is_none() {
+                if let Some(inboxes) = cache.as_ref().and_then(|c| c.inboxes()) {
+                    return Ok(Some(WorkerEvent::Inboxes(inboxes)));
+                }
+            }
+            let inboxes = email::list_inboxes(logged_in(session)?)?;
+            if let Some(c) = cache {
+                c.store_inboxes(&inboxes);
+            }
+            Ok(Some(WorkerEvent::Inboxes(inboxes)))
+        }
+        Command::FetchEmails { inbox, n } => {
+            if session.is_none() {
+                if let Some((entries, listing)) = cache.as_ref().and_then(|c| c.listing(&inbox)) {
+                    return Ok(Some(WorkerEvent::Emails { entries, listing }));
+                }
+            }
+            let s = logged_in(session)?;
+            let tracking = email::tracking(s)?;
+            let highest_modseq = match tracking {
+                Tracking::Compare => None,
+                Tracking::CondStore | Tracking::QResync => email::highest_modseq(s, &inbox)?,
+            };
+            let mb = email::select(s, inbox.clone())?;
+            let uid_validity = mb.uid_validity.unwrap_or_default();
+            let listing = Listing {
+                account: 0,
+                inbox: inbox.clone(),
+                uid_validity,
+                total: mb.exists as usize,
+                criteria: None,
+            };
+            let synced = cache.as_ref().and_then(|c| c.synced(&inbox, uid_validity));
+            if let (Some(c), Some(synced)) = (&cache, synced) {
+                match sync(s, tracking, &synced, &listing, n, cancel, respond)? {
+                    Sync::Changes(mut changes) => {
+                        changes.highest_modseq = highest_modseq;
+                        c.apply(&inbox, uid_validity, &changes);
+                        if let Some((entries, _)) = c.listing(&inbox) {
+                            return Ok(Some(WorkerEvent::Emails { entries, listing }));
+                        }
+                    }
+                    Sync::Cancelled => return Ok(None),
+                    Sync::TooFarBehind => {}
+                }
+            }
+            let total = cmp::min(mb.exists, n);
+            let mut entries = Vec::with_capacity(total as usize);
+            let mut hi = mb.exists;
+            while hi > mb.exists - total {
+                if cancel.load(Ordering::Relaxed) {
+                    return Ok(None);
+                }
+                let lo = cmp::max(hi.saturating_sub(FETCH_CHUNK), mb.exists - total) + 1;
+                let chunk =
+                    email::fetch_envelopes(s, format!("{}:{}", lo, hi), &inbox, uid_validity)?;
+                entries.extend(chunk);
+                respond(WorkerEvent::Progress {
+                    done: entries.len(),
+                    total: total as usize,
+                });
+                hi = lo - 1;
+            }
+            if let Some(c) = cache {
+                c.replace(&inbox, uid_validity, &entries, highest_modseq);
+            }
+            Ok(Some(WorkerEvent::Emails { entries, listing }))
+        }
+        Command::Search { inbox, criteria, n } => {
+            let s = logged_in(session)?;
+            let mb = email::select(s, inbox.clone())?;
+            let uid_validity = mb.uid_validity.unwrap_or_default();
+            let mut uids = email::search(s, criteria.clone())?;
+            let total = uids.len();
+            uids.truncate(n);
+            match fetch_uids(s, &uids, &inbox, uid_validity, cancel, respond)? {
+                Some(entries) => Ok(Some(WorkerEvent::Emails {
+                    entries,
+                    listing: Listing {
+                        account: 0,
+                        inbox,
+                        uid_validity,
+                        total,
+                        criteria: Some(criteria),
+                    },
+                })),
+                None => Ok(None),
+            }
+        }
+        Command::FetchMore {
+            inbox,
+            uid_validity,
+            criteria,
+            before_uid,
+            n,
+        } => {
+            let s = logged_in(session)?;
+            email::select_uid_validity(s, inbox.clone(), uid_validity)?;
+            if before_uid <= 1 {
+                return Ok(Some(WorkerEvent::MoreEmails(vec![])));
+            }
+            // Search results would leave gaps in the cache
+            let cacheable = criteria.is_none();
+            let criteria = match criteria {
+                // After the criteria, which may start with a CHARSET that has to come first
+                Some(c) => format!("{} UID 1:{}", c, before_uid - 1),
+                None => format!("UID 1:{}", before_uid - 1),
+            };
+            let mut uids = email::search(s, criteria)?;
+            uids.truncate(n);
+            let entries = fetch_uids(s, &uids, &inbox, uid_validity, cancel, respond)?;
+            if let (Some(c), Some(entries), true) = (&cache, &entries, cacheable) {
+                c.append(entries);
+            }
+            match entries {
+                Some(entries) => Ok(Some(WorkerEvent::MoreEmails(entries))),
+                None => Ok(None),
+            }
+        }
+        Command::FetchPreview {
+            inbox,
+            uid,
+            uid_validity,
+        } => {
+            let body = fetch_body(session, cache, &inbox, uid, uid_validity)?;
+            Ok(Some(WorkerEvent::Preview {
+                inbox,
+                uid,
+                uid_validity,
+                html: body
+                    .map(|b| email::html_body(&b))
+                    .transpose()?
+                    .unwrap_or_default(),
+            }))
+        }
+        Command::FetchMessage {
+            inbox,
+            uid,
+            uid_validity,
+        } => {
+            let raw = fetch_body(session, cache, &inbox, uid, uid_validity)?
+                .ok_or(Error::Protocol(format!("message {} is gone", uid)))?;
+            Ok(Some(WorkerEvent::Message { inbox, uid, raw }))
+        }
+        Command::SetFlag {
+            inbox,
+            uid_validity,
+            uids,
+            flag,
+            add,
+        } => {
+            let flags = email::set_flag(
+                logged_in(session)?,
+                inbox.clone(),
+                uid_validity,
+                &uids,
+                flag,
+                add,
+            )?;
+            if let Some(c) = cache {
+                c.update_flags(&inbox, uid_validity, &flags);
+            }
+            Ok(Some(WorkerEvent::Flags { inbox, flags }))
+        }
+        Command::Expunge { inbox } => {
+            email::expunge(logged_in(session)?, inbox.clone())?;
+            if let Some(c) = cache {
+                c.remove_deleted(&inbox);
+            }
+            Ok(Some(WorkerEvent::Expunged { inbox }))
+        }
+        Command::Transfer {
+            inbox,
+            uid_validity,
+            uids,
+            dest,
+            op,
+        } => {
+            email::transfer(
+                logged_in(session)?,
+                inbox.clone(),
+                uid_validity,
+                &uids,
+                dest.clone(),
+                op,
+            )?;
+            if let (Some(c), Transfer::Move) = (&cache, op) {
+                c.remove(&inbox, uid_validity, &uids);
+            }
+            Ok(Some(WorkerEvent::Transferred {
+                inbox,
+                uids,
+                op,
+                dest,
+            }))
+        }
+        Command::Send { account, message } => {
+            smtp::send(&account, &message)?;
+            // Failing to keep a copy is reported along with success, so that it isn't sent again
+            let saved = account.sent_folder.map(|folder| {
+                let s = logged_in(session)?;
+                email::append(s, &folder, &message.formatted())?;
+                Ok(folder)
+            });
+            Ok(Some(WorkerEvent::Sent { saved }))
+        }
+    }
+}